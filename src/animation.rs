@@ -0,0 +1,220 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Hashing of every frame of animated GIFs and PNGs (APNG), with the `rust-image` feature.
+use bit_vec::BitVec;
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, Frames, ImageFormat, ImageReader, ImageResult};
+
+use {Hasher, ImageHash};
+
+use std::io::{BufRead, BufReader, Seek};
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+/// Delays at or below this are played at `SHORT_DELAY_REPLACEMENT_MS` by browsers, so they are
+/// weighted as such in `AnimationHash::combined()`.
+const SHORT_DELAY_MS: u64 = 10;
+const SHORT_DELAY_REPLACEMENT_MS: u64 = 100;
+
+/// The hash of one frame of an animation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnimationFrame {
+    /// How long the frame is shown for.
+    pub delay: Duration,
+    /// The hash of the whole canvas while the frame is shown.
+    pub hash: ImageHash,
+}
+
+/// The hashes of all frames of an animation.
+///
+/// Still images are treated as animations with a single frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnimationHash {
+    /// The frames, in the order they are shown.
+    pub frames: Vec<AnimationFrame>,
+}
+
+impl AnimationHash {
+    /// Decode and hash every frame of the image at `path`.
+    ///
+    /// The format is guessed from the file contents; GIF and APNG files are hashed frame by
+    /// frame and anything else `image` can decode as a single frame.
+    pub fn open<P: AsRef<Path>>(path: P, hasher: &Hasher) -> ImageResult<Self> {
+        Self::load(BufReader::new(File::open(path)?), hasher)
+    }
+
+    /// Decode and hash every frame of the image read from `reader`, as with `open()`.
+    pub fn load<R: BufRead + Seek>(reader: R, hasher: &Hasher) -> ImageResult<Self> {
+        let reader = ImageReader::new(reader).with_guessed_format()?;
+
+        match reader.format() {
+            Some(ImageFormat::Gif) =>
+                Self::from_frames(GifDecoder::new(reader.into_inner())?.into_frames(), hasher),
+            Some(ImageFormat::Png) => {
+                let decoder = PngDecoder::new(reader.into_inner())?;
+
+                if decoder.is_apng()? {
+                    Self::from_frames(decoder.apng()?.into_frames(), hasher)
+                } else {
+                    let image = ::image::DynamicImage::from_decoder(decoder)?;
+                    Ok(Self::still(hasher.hash(&image)))
+                }
+            },
+            _ => Ok(Self::still(hasher.hash(&reader.decode()?))),
+        }
+    }
+
+    /// Hash every frame yielded by an `image::AnimationDecoder`.
+    pub fn from_frames(frames: Frames, hasher: &Hasher) -> ImageResult<Self> {
+        let frames = frames.map(|frame| {
+            let frame = frame?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+
+            Ok(AnimationFrame {
+                delay: Duration::from_secs_f64(numer as f64 / denom.max(1) as f64 / 1000.),
+                hash: hasher.hash(frame.buffer()),
+            })
+        }).collect::<ImageResult<_>>()?;
+
+        Ok(AnimationHash { frames })
+    }
+
+    fn still(hash: ImageHash) -> Self {
+        AnimationHash {
+            frames: vec![AnimationFrame { delay: Duration::from_secs(0), hash }],
+        }
+    }
+
+    /// Combine the frame hashes into a single hash of the whole animation, by taking the
+    /// majority of each bit over all frames, weighted by how long each is shown.
+    ///
+    /// Delays of 10 ms or less count as 100 ms, which is how browsers play them.
+    /// Returns `None` if there are no frames.
+    pub fn combined(&self) -> Option<ImageHash> {
+        let first = &self.frames.first()?.hash;
+        let mut weights = vec![0u64; first.bitv.len()];
+        let mut total = 0;
+
+        for frame in &self.frames {
+            let mut delay = frame.delay.as_millis() as u64;
+
+            if delay <= SHORT_DELAY_MS {
+                delay = SHORT_DELAY_REPLACEMENT_MS;
+            }
+
+            for (weight, bit) in weights.iter_mut().zip(frame.hash.bitv.iter()) {
+                *weight += delay * bit as u64;
+            }
+
+            total += delay;
+        }
+
+        Some(ImageHash {
+            bitv: weights.iter().map(|&weight| weight * 2 > total).collect::<BitVec>(),
+            hash_type: first.hash_type,
+        })
+    }
+
+    /// The average distance in bits between the frames of the shorter of this animation and
+    /// `other` and the frames they best match in the longer one, in order.
+    ///
+    /// Frame delays are ignored and frames of the longer animation may be skipped or matched
+    /// more than once, so animations that only differ in timing or by dropped (or duplicated)
+    /// frames have a distance of zero.
+    ///
+    /// Returns `None` if either animation has no frames. Both must have been hashed with the
+    /// same `Hasher` settings.
+    pub fn dist(&self, other: &AnimationHash) -> Option<f64> {
+        let (short, long) = if self.frames.len() <= other.frames.len() {
+            (&self.frames, &other.frames)
+        } else {
+            (&other.frames, &self.frames)
+        };
+
+        if short.is_empty() {
+            return None;
+        }
+
+        // `prev_row[j]` is the lowest total distance of the frames so far, with the last one
+        // matched to one of `long[..= j]`.
+        let mut prev_row = vec![0; long.len()];
+        let mut row = prev_row.clone();
+
+        for short_frame in short {
+            let mut lowest = usize::MAX;
+
+            for (j, long_frame) in long.iter().enumerate() {
+                lowest = lowest.min(prev_row[j] + short_frame.hash.dist(&long_frame.hash));
+                row[j] = lowest;
+            }
+
+            ::std::mem::swap(&mut prev_row, &mut row);
+        }
+
+        Some(prev_row[long.len() - 1] as f64 / short.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::{weak_rng, Rng};
+
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, RgbaImage};
+
+    use super::*;
+
+    use HashType;
+
+    use std::io::Cursor;
+
+    fn encode_gif(frames: &[(RgbaImage, u32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        GifEncoder::new(&mut out).encode_frames(frames.iter().map(|&(ref buf, delay_ms)| {
+            Frame::from_parts(buf.clone(), 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))
+        })).unwrap();
+
+        out
+    }
+
+    #[test]
+    fn dropped_and_retimed_frames() {
+        let mut rng = weak_rng();
+
+        let frames: Vec<_> = (0 .. 6).map(|_| {
+            let mut buf = vec![0; 32 * 32 * 4];
+            rng.fill_bytes(&mut buf);
+            RgbaImage::from_raw(32, 32, buf).unwrap()
+        }).collect();
+
+        let original: Vec<_> = frames.iter().map(|buf| (buf.clone(), 50)).collect();
+        let retimed: Vec<_> = frames.iter().enumerate()
+            .filter(|&(i, _)| i != 2 && i != 4)
+            .map(|(i, buf)| (buf.clone(), 20 + i as u32 * 30))
+            .collect();
+
+        let hasher = Hasher::new(8, HashType::Gradient);
+        let original = AnimationHash::load(Cursor::new(encode_gif(&original)), &hasher).unwrap();
+        let retimed = AnimationHash::load(Cursor::new(encode_gif(&retimed)), &hasher).unwrap();
+        let reversed = AnimationHash {
+            frames: original.frames.iter().rev().cloned().collect(),
+        };
+
+        assert_eq!(original.frames.len(), 6);
+        assert_eq!(original.frames[0].delay, Duration::from_millis(50));
+        assert_eq!(retimed.frames.len(), 4);
+        assert_eq!(original.dist(&retimed), Some(0.));
+        assert!(original.dist(&reversed).unwrap() > 10.);
+    }
+}
@@ -0,0 +1,520 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Command-line front end for `img_hash`. Requires the `cli` feature.
+//!
+//! ```notest
+//! img_hash hash [-a ALG] [-s SIZE] [-e] [--trim] [-c CACHE] [-f base64|hex|json] <files...>
+//! img_hash compare [-a ALG] [-s SIZE] [-e] [--trim] <a> <b>
+//! img_hash dupes [-a ALG] [-s SIZE] [-e] [--trim] [-c CACHE] [-t THRESHOLD] <dir>
+//! ```
+//!
+//! Exits with status 1 if any file could not be read or decoded, 2 on usage errors.
+extern crate image;
+extern crate img_hash;
+extern crate rustc_serialize as serialize;
+
+use img_hash::cache::HashCache;
+use img_hash::{HashType, Hasher, ImageHash, TrimBorders};
+
+use serialize::base64::FromBase64;
+use serialize::hex::ToHex;
+
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs, process};
+
+const USAGE: &str = "\
+Usage:
+    img_hash hash [options] <files...>
+    img_hash compare [options] <a> <b>
+    img_hash dupes [options] [-t THRESHOLD] <dir>
+
+Options:
+    -a, --alg ALG          mean, block, gradient, double-gradient, vertical-gradient,
+                           diagonal-gradient or dct [default: gradient]
+    -s, --size SIZE        hash size, 1 to 64; hashes have SIZE * SIZE bits [default: 8]
+    -f, --format FORMAT    output of `hash`: base64, hex or json [default: base64];
+                           all three include the algorithm
+    -t, --threshold DIST   max. distance in bits for `dupes` to group two images [default: 0]
+    -c, --cache FILE       reuse hashes of unchanged files from FILE and save new ones to it
+    -e, --exif             rotate and mirror images as their EXIF orientation says before hashing
+        --trim             crop uniform borders, such as black bars, before hashing
+    -h, --help             print this message";
+
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "bmp", "gif", "ico", "jpeg", "jpg", "png", "pnm", "pbm", "pgm", "ppm", "tga", "tif", "tiff", "webp",
+];
+
+const MAX_HASH_SIZE: u32 = 64;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(run(&args));
+}
+
+/// Run the command line `args`, returning the exit status.
+fn run(args: &[String]) -> i32 {
+    match Args::parse(args) {
+        Ok(args) => args.run(),
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            2
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Command {
+    Hash,
+    Compare,
+    Dupes,
+    Help,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Base64,
+    Hex,
+    Json,
+}
+
+struct Args {
+    command: Command,
+    hasher: Hasher,
+    format: Format,
+    threshold: usize,
+    cache: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, String> {
+        let command = match args.first().map(|s| &**s) {
+            Some("hash") => Command::Hash,
+            Some("compare") => Command::Compare,
+            Some("dupes") => Command::Dupes,
+            Some("-h") | Some("--help") | Some("help") => Command::Help,
+            Some(other) => return Err(format!("unknown command `{}`", other)),
+            None => return Err("missing command".into()),
+        };
+
+        let mut hash_type = HashType::Gradient;
+        let mut hash_size = 8;
+        let mut format = Format::Base64;
+        let mut threshold = 0;
+        let mut cache = None;
+        let mut exif = false;
+        let mut trim = false;
+        let mut paths = Vec::new();
+
+        let mut args = args[1..].iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for `{}`", arg));
+
+            match &**arg {
+                "-a" | "--alg" => hash_type = parse_hash_type(value()?)?,
+                "-s" | "--size" => hash_size = parse_num(arg, value()?)?,
+                "-f" | "--format" => format = match &**value()? {
+                    "base64" => Format::Base64,
+                    "hex" => Format::Hex,
+                    "json" => Format::Json,
+                    other => return Err(format!("unknown format `{}`", other)),
+                },
+                "-t" | "--threshold" => threshold = parse_num(arg, value()?)?,
+                "-c" | "--cache" => cache = Some(PathBuf::from(value()?)),
+                "-e" | "--exif" => exif = true,
+                "--trim" => trim = true,
+                "-h" | "--help" => return Ok(Args::help()),
+                "--" => paths.extend(args.by_ref().map(PathBuf::from)),
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("unknown option `{}`", flag))
+                },
+                path => paths.push(PathBuf::from(path)),
+            }
+        }
+
+        if hash_size == 0 || hash_size > MAX_HASH_SIZE {
+            return Err(format!("hash size must be between 1 and {}", MAX_HASH_SIZE));
+        }
+
+        match (command, paths.len()) {
+            (Command::Hash, 0) => return Err("`hash` needs at least one file".into()),
+            (Command::Compare, 2) => (),
+            (Command::Compare, _) => return Err("`compare` needs exactly two files".into()),
+            (Command::Dupes, 1) => (),
+            (Command::Dupes, _) => return Err("`dupes` needs exactly one directory".into()),
+            _ => (),
+        }
+
+        let mut hasher = Hasher::new(hash_size, hash_type).exif_orientation(exif);
+
+        if trim {
+            hasher = hasher.trim_borders(TrimBorders::new());
+        }
+
+        Ok(Args {
+            command,
+            hasher,
+            format,
+            threshold,
+            cache,
+            paths,
+        })
+    }
+
+    fn help() -> Args {
+        Args {
+            command: Command::Help,
+            hasher: Hasher::new(8, HashType::Gradient),
+            format: Format::Base64,
+            threshold: 0,
+            cache: None,
+            paths: vec![],
+        }
+    }
+
+    fn run(self) -> i32 {
+        match self.command {
+            Command::Hash => self.hash(),
+            Command::Compare => self.compare(),
+            Command::Dupes => self.dupes(),
+            Command::Help => {
+                println!("{}", USAGE);
+                0
+            },
+        }
+    }
+
+    fn hash(&self) -> i32 {
+        let mut cache = match self.open_cache() {
+            Ok(cache) => cache,
+            Err(code) => return code,
+        };
+
+        let mut failed = false;
+        let mut json_entries = Vec::new();
+
+        for path in &self.paths {
+            let hash = match hash_file(&self.hasher, &mut cache, path) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failed = true;
+                    continue;
+                }
+            };
+
+            match self.format {
+                Format::Base64 => println!("{}  {}", hash.to_base64(), path.display()),
+                Format::Hex => println!("{}  {}", to_hex(&hash), path.display()),
+                Format::Json => json_entries.push(format!(
+                    "{{\"path\":{},\"type\":{},\"size\":{},\"hash\":{}}}",
+                    json_str(&path.to_string_lossy()),
+                    json_str(&format!("{:?}", hash.hash_type)),
+                    hash.size(),
+                    json_str(&hash.to_base64())
+                )),
+            }
+        }
+
+        if let Format::Json = self.format {
+            println!("[{}]", json_entries.join(","));
+        }
+
+        (self.save_cache(&cache) || failed) as i32
+    }
+
+    fn compare(&self) -> i32 {
+        let mut cache = HashCache::new();
+
+        let hashes = self.paths.iter()
+            .map(|path| hash_file(&self.hasher, &mut cache, path))
+            .collect::<Result<Vec<_>, _>>();
+
+        match hashes {
+            Ok(hashes) => {
+                println!("distance: {}", hashes[0].dist(&hashes[1]));
+                println!("ratio: {}", hashes[0].dist_ratio(&hashes[1]));
+                0
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        }
+    }
+
+    fn dupes(&self) -> i32 {
+        let mut cache = match self.open_cache() {
+            Ok(cache) => cache,
+            Err(code) => return code,
+        };
+
+        let mut files = Vec::new();
+        let mut failed = false;
+
+        if let Err(e) = walk_images(&self.paths[0], &mut files) {
+            eprintln!("{}: {}", self.paths[0].display(), e);
+            return 1;
+        }
+
+        files.sort();
+
+        let mut hashed = Vec::with_capacity(files.len());
+
+        for path in files {
+            match hash_file(&self.hasher, &mut cache, &path) {
+                Ok(hash) => hashed.push((path, hash)),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    failed = true;
+                }
+            }
+        }
+
+        for group in group_dupes(&hashed, self.threshold) {
+            for idx in group {
+                println!("{}", hashed[idx].0.display());
+            }
+
+            println!();
+        }
+
+        (self.save_cache(&cache) || failed) as i32
+    }
+
+    fn open_cache(&self) -> Result<HashCache, i32> {
+        match self.cache {
+            Some(ref file) => HashCache::open(file).map_err(|e| {
+                eprintln!("{}: {}", file.display(), e);
+                1
+            }),
+            None => Ok(HashCache::new()),
+        }
+    }
+
+    /// Returns `true` if saving failed.
+    fn save_cache(&self, cache: &HashCache) -> bool {
+        let result = cache.save();
+
+        if let Err(ref e) = result {
+            eprintln!("{}: {}", self.cache.as_ref().unwrap().display(), e);
+        }
+
+        result.is_err()
+    }
+}
+
+fn parse_hash_type(name: &str) -> Result<HashType, String> {
+    Ok(match name {
+        "mean" => HashType::Mean,
+        "block" => HashType::Block,
+        "gradient" => HashType::Gradient,
+        "double-gradient" => HashType::DoubleGradient,
+        "vertical-gradient" => HashType::VerticalGradient,
+        "diagonal-gradient" => HashType::DiagonalGradient,
+        "dct" => HashType::DCT,
+        _ => return Err(format!("unknown hash algorithm `{}`", name)),
+    })
+}
+
+fn parse_num<T: std::str::FromStr>(flag: &str, val: &str) -> Result<T, String> {
+    val.parse().map_err(|_| format!("invalid value `{}` for `{}`", val, flag))
+}
+
+/// An error reading or decoding a file, with the path it happened on.
+struct FileError {
+    path: PathBuf,
+    error: image::ImageError,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+fn hash_file(hasher: &Hasher, cache: &mut HashCache, path: &Path) -> Result<ImageHash, FileError> {
+    cache.get_or_hash(hasher, path, |path| hasher.decode_path(path))
+        .map_err(|error| FileError { path: path.to_owned(), error })
+}
+
+/// Collect the images under `dir`, without following symlinks to directories, which may loop.
+fn walk_images(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            walk_images(&path, out)?;
+        } else if !path.is_dir() && has_image_extension(&path) {
+            out.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn has_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(ext)))
+}
+
+/// Group the indices of all hashes within `threshold` bits of each other, transitively.
+///
+/// Only groups with at least two members are returned.
+fn group_dupes(hashed: &[(PathBuf, ImageHash)], threshold: usize) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0 .. hashed.len()).collect();
+
+    fn root(parents: &mut [usize], mut idx: usize) -> usize {
+        while parents[idx] != idx {
+            parents[idx] = parents[parents[idx]];
+            idx = parents[idx];
+        }
+
+        idx
+    }
+
+    for i in 0 .. hashed.len() {
+        for j in i + 1 .. hashed.len() {
+            if hashed[i].1.dist(&hashed[j].1) <= threshold {
+                let (root_i, root_j) = (root(&mut parents, i), root(&mut parents, j));
+                parents[root_j] = root_i;
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = vec![vec![]; hashed.len()];
+
+    for idx in 0 .. hashed.len() {
+        let root = root(&mut parents, idx);
+        groups[root].push(idx);
+    }
+
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// The bytes of `hash.to_base64()`, hash type first, in hex.
+fn to_hex(hash: &ImageHash) -> String {
+    hash.to_base64().from_base64().expect("`to_base64()` is valid Base64").to_hex()
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    extern crate bit_vec;
+
+    use self::bit_vec::BitVec;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    fn hash(byte: u8) -> ImageHash {
+        ImageHash { bitv: BitVec::from_bytes(&[byte]), hash_type: HashType::Gradient }
+    }
+
+    #[test]
+    fn parses_args() {
+        let parsed = Args::parse(&args(&["hash", "-a", "dct", "--size", "16", "-f", "hex", "a.png", "--", "-b.png"]))
+            .unwrap();
+        assert_eq!(parsed.hasher.fingerprint(), "dct:16");
+        assert_eq!(parsed.paths, [PathBuf::from("a.png"), PathBuf::from("-b.png")]);
+
+        for &size in &["1", "64"] {
+            assert!(Args::parse(&args(&["hash", "-s", size, "a.png"])).is_ok());
+        }
+
+        for bad in &[&["hash", "-s", "0", "a.png"][..], &["hash", "-s", "65", "a.png"], &["hash", "-s", "x", "a.png"],
+                     &["hash", "-a", "sha1", "a.png"], &["hash", "-q", "a.png"], &["compare", "a.png"],
+                     &["dupes"], &["hash", "a.png", "-s"], &["frobnicate"], &[]] {
+            assert_eq!(run(&args(bad)), 2, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn groups_dupes_transitively() {
+        // 0 and 1 differ by one bit, 1 and 2 by one bit, 0 and 2 by two; 3 is far from all.
+        let hashed: Vec<_> = [0b0000_0000, 0b0000_0001, 0b0000_0011, 0b1111_0000].iter().enumerate()
+            .map(|(i, &byte)| (PathBuf::from(i.to_string()), hash(byte)))
+            .collect();
+
+        assert_eq!(group_dupes(&hashed, 0), Vec::<Vec<usize>>::new());
+        assert_eq!(group_dupes(&hashed, 1), [vec![0, 1, 2]]);
+        assert_eq!(group_dupes(&hashed, 4), [vec![0, 1, 2, 3]]);
+
+        let mut hashed = hashed;
+        hashed.push((PathBuf::from("4"), hash(0b1111_0001)));
+        assert_eq!(group_dupes(&hashed, 1), [vec![0, 1, 2], vec![3, 4]]);
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_str("plain.png"), r#""plain.png""#);
+        assert_eq!(json_str("a \"b\" \\ c"), r#""a \"b\" \\ c""#);
+        assert_eq!(json_str("line\nbreak\ttab\r"), r#""line\nbreak\ttab\r""#);
+        assert_eq!(json_str("\u{1}\u{1f}é"), r#""\u0001\u001fé""#);
+    }
+
+    #[test]
+    fn hex_includes_hash_type() {
+        let gradient = hash(0xa5);
+        let mean = ImageHash { hash_type: HashType::Mean, .. gradient.clone() };
+
+        assert_eq!(to_hex(&gradient).len(), 4);
+        assert!(to_hex(&gradient).ends_with("a5"));
+        assert_ne!(to_hex(&gradient), to_hex(&mean));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_skips_symlinked_dirs() {
+        use std::os::unix::fs::symlink;
+
+        let dir = env::temp_dir().join(format!("img_hash_walk_test_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub").join("a.png"), b"").unwrap();
+        fs::write(dir.join("notes.txt"), b"").unwrap();
+
+        // A link back up to the top: following it would recurse forever.
+        symlink(&dir, dir.join("sub").join("loop")).unwrap();
+        symlink(dir.join("sub").join("a.png"), dir.join("b.png")).unwrap();
+
+        let mut found = Vec::new();
+        walk_images(&dir, &mut found).unwrap();
+        found.sort();
+
+        assert_eq!(found, [dir.join("b.png"), dir.join("sub").join("a.png")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -11,6 +11,8 @@
 // Main site: http://blockhash.io
 use super::HashImage;
 
+use dihedral::Orientation;
+
 use bit_vec::BitVec;
 
 use std::cmp::Ordering;
@@ -18,21 +20,9 @@ use std::mem;
 
 const FLOAT_EQ_MARGIN: f64 = 0.001;
 
-pub fn blockhash<I: HashImage>(img: &I, size: u32) -> BitVec {
-    let size = next_multiple_of_4(size);
-    let (width, height) = img.dimensions(); 
-
-    // Skip the floating point math if it's unnecessary
-    if width % size == 0 && height % size == 0 {
-        blockhash_fast(img, size)
-    } else {
-        blockhash_slow(img, size)
-    }        
-} 
-
 macro_rules! gen_hash {
-    ($imgty:ty, $valty:ty, $blocks: expr, $size:expr, $block_width:expr, $block_height:expr, $eq_fn:expr) => ({
-        let channel_count = <$imgty as HashImage>::channel_count() as u32;
+    ($channel_count:expr, $valty:ty, $blocks: expr, $size:expr, $block_width:expr, $block_height:expr, $eq_fn:expr) => ({
+        let channel_count = $channel_count;
 
         let group_len = (($size * $size) / 4) as usize;
 
@@ -59,24 +49,266 @@ macro_rules! gen_hash {
     })
 }
 
-fn blockhash_slow<I: HashImage>(img: &I, size: u32) -> BitVec {
-    let mut blocks = vec![0f64; (size * size) as usize];
+/// The two methods of the reference blockhash.io implementations, for
+/// `Hasher::blockhash_method()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockhashMethod {
+    /// Method 1: sums whole, non-overlapping blocks of `width / bits` by `height / bits` pixels,
+    /// leaving out the pixels left over at the right and bottom edges.
+    Quick,
+    /// Method 2, the default of the reference tools: where a block boundary falls inside a
+    /// pixel, splits the pixel between the blocks on either side. The same as `Quick` if the
+    /// width and height are multiples of the number of blocks.
+    Precise,
+}
+
+impl BlockhashMethod {
+    /// A short string identifying this method in `Hasher::fingerprint()`.
+    pub(crate) fn fingerprint(self) -> &'static str {
+        match self {
+            BlockhashMethod::Quick => "quick",
+            BlockhashMethod::Precise => "precise",
+        }
+    }
+}
+
+/// The sums of the pixels in each block of an image, before comparing them to the medians.
+pub struct BlockSums {
+    size: u32,
+    channel_count: u32,
+    sums: Sums,
+    /// Compare to the medians exactly as the reference implementations do.
+    reference: bool,
+}
+
+enum Sums {
+    Exact { blocks: Vec<u32>, block_width: u32, block_height: u32 },
+    Float { blocks: Vec<f64>, block_width: f64, block_height: f64 },
+}
+
+pub fn block_sums<I: HashImage>(img: &I, size: u32) -> BlockSums {
+    legacy_block_sums(&Image(img), size, I::channel_count() as u32)
+}
+
+fn legacy_block_sums<P: PixelSums>(pixels: &P, size: u32, channel_count: u32) -> BlockSums {
+    let size = next_multiple_of_4(size);
+    let (width, height) = pixels.dimensions(); 
 
+    // Skip the floating point math if it's unnecessary
+    let sums = if width % size == 0 && height % size == 0 {
+        blockhash_fast(pixels, size)
+    } else {
+        blockhash_slow(pixels, size)
+    };
+
+    BlockSums {
+        size,
+        channel_count,
+        sums,
+        reference: false,
+    }
+} 
+
+/// The block sums of `img` as computed by the reference implementations with `method`.
+///
+/// These see every image as 8-bit RGB or RGBA: grayscale counts three times, and alpha only
+/// matters for pixels with 4 channels, fully transparent ones counting as white.
+pub fn reference_block_sums<I: HashImage>(img: &I, size: u32, method: BlockhashMethod) -> BlockSums {
+    let (width, height) = img.dimensions();
+    reference_sums(&reference_values(img), width, height, size, method)
+}
+
+/// The block sums of `img` in each orientation, in the order of `Orientation::ALL`: the same
+/// as those of the actually turned image, with the reference `method` or without.
+pub fn dihedral_block_sums<I: HashImage>(img: &I, size: u32, method: Option<BlockhashMethod>) -> Vec<BlockSums> {
     let (width, height) = img.dimensions();
+    let orientations = Orientation::ALL.iter().cloned();
+
+    // Whole blocks of a square image sum to integers, which turn exactly with the blocks.
+    if width == height && width % next_multiple_of_4(size) == 0 && (method.is_some() || !img.is_wide()) {
+        let sums = match method {
+            Some(method) => reference_block_sums(img, size, method),
+            None => block_sums(img, size),
+        };
+
+        return orientations.map(|orientation| sums.transformed(orientation)).collect();
+    }
+
+    // Otherwise sum the blocks of each orientation again, from the pixels turned.
+    let turned_size = |orientation: Orientation| {
+        if orientation.swaps_axes() { (height, width) } else { (width, height) }
+    };
+
+    match method {
+        Some(method) => {
+            let values = reference_values(img);
+
+            orientations.map(|orientation| {
+                let turned = orientation.transform_grid(&values, width as usize, height as usize);
+                let (width, height) = turned_size(orientation);
+                reference_sums(&turned, width, height, size, method)
+            }).collect()
+        },
+        None => {
+            let grid = SumGrid::new(img);
+
+            orientations.map(|orientation| {
+                legacy_block_sums(&grid.turned(orientation), size, I::channel_count() as u32)
+            }).collect()
+        },
+    }
+}
+
+/// The sum of the channels of each pixel of `img`, row by row, as the reference
+/// implementations see them.
+fn reference_values<I: HashImage>(img: &I) -> Vec<u32> {
+    let (width, height) = img.dimensions();
+    let mut values = vec![0u32; width as usize * height as usize];
+
+    img.foreach_pixel(|x, y, px| {
+        values[(y * width + x) as usize] = match px.len() {
+            4 => if px[3] == 0 { 255 * 3 } else { sum_px(&px[..3]) },
+            3 => sum_px(px),
+            2 | 1 => px[0] as u32 * 3,
+            _ => panic!("Channel count was different than actual pixel size"),
+        };
+    });
+
+    values
+}
+
+/// The reference block sums of an image `width` by `height` pixels, `values` from
+/// `reference_values()`.
+fn reference_sums(values: &[u32], width: u32, height: u32, size: u32, method: BlockhashMethod) -> BlockSums {
+    let size = next_multiple_of_4(size);
+    let even = width % size == 0 && height % size == 0;
+
+    let sums = if method == BlockhashMethod::Quick || even {
+        let (block_width, block_height) = (width / size, height / size);
+        let mut blocks = vec![0f64; (size * size) as usize];
+
+        for (i, block) in blocks.iter_mut().enumerate() {
+            let (block_x, block_y) = (i as u32 % size, i as u32 / size);
+
+            *block = (block_y * block_height .. (block_y + 1) * block_height)
+                .flat_map(|y| {
+                    let row = (y * width + block_x * block_width) as usize;
+                    &values[row .. row + block_width as usize]
+                })
+                .map(|&value| value as u64)
+                .sum::<u64>() as f64;
+        }
+
+        Sums::Float { blocks, block_width: block_width as f64, block_height: block_height as f64 }
+    } else {
+        let (block_width, block_height) = (width as f64 / size as f64, height as f64 / size as f64);
+        let mut blocks = vec![0f64; (size * size) as usize];
+
+        let idx = |x, y| (y * size + x) as usize;
+
+        // In the same order as the reference, to round the same.
+        for y in 0 .. height {
+            let (top, bottom, weight_top, weight_bottom) = block_span(y, height, block_height);
+
+            for x in 0 .. width {
+                let (left, right, weight_left, weight_right) = block_span(x, width, block_width);
+                let value = values[(y * width + x) as usize] as f64;
+
+                blocks[idx(left, top)] += value * weight_top * weight_left;
+                blocks[idx(right, top)] += value * weight_top * weight_right;
+                blocks[idx(left, bottom)] += value * weight_bottom * weight_left;
+                blocks[idx(right, bottom)] += value * weight_bottom * weight_right;
+            }
+        }
+
+        Sums::Float { blocks, block_width, block_height }
+    };
+
+    BlockSums {
+        size,
+        channel_count: 4,
+        sums,
+        reference: true,
+    }
+}
+
+impl BlockSums {
+    pub fn to_bits(&self) -> BitVec {
+        match self.sums {
+            Sums::Float { ref blocks, block_width, block_height } if self.reference =>
+                reference_bits(blocks, block_width * block_height),
+            Sums::Exact { ref blocks, block_width, block_height } =>
+                gen_hash!(self.channel_count, u32, blocks, self.size, block_width, block_height,
+                    |l, r| l == r),
+            Sums::Float { ref blocks, block_width, block_height } =>
+                gen_hash!(self.channel_count, f64, blocks, self.size, block_width, block_height,
+                    |l: f64, r: f64| (l - r).abs() < FLOAT_EQ_MARGIN),
+        }
+    }
+
+    /// The mean brightness of each block in `[0, 1]`, paired with the median of its quarter
+    /// of the blocks that `to_bits()` compares it against.
+    pub fn levels(&self) -> Vec<(f64, f64)> {
+        let (blocks, block_area): (Vec<f64>, f64) = match self.sums {
+            Sums::Exact { ref blocks, block_width, block_height } =>
+                (blocks.iter().map(|&block| block as f64).collect(), (block_width * block_height) as f64),
+            Sums::Float { ref blocks, block_width, block_height } =>
+                (blocks.clone(), block_width * block_height),
+        };
+
+        let px_max = if self.reference || self.channel_count > 2 { 255. * 3. } else { 255. };
+        let block_max = px_max * block_area;
+
+        blocks.chunks(blocks.len() / 4)
+            .flat_map(|band| {
+                let median = if self.reference { reference_median(band) } else { get_median(band) };
+                band.iter().map(move |&block| (block / block_max, median / block_max))
+            })
+            .collect()
+    }
+
+    /// The block sums of the image turned to `orientation`, where turning the blocks turns
+    /// the image; see `dihedral_block_sums()`.
+    fn transformed(&self, orientation: Orientation) -> BlockSums {
+        let size = self.size as usize;
+        let swap = orientation.swaps_axes();
+
+        let sums = match self.sums {
+            Sums::Exact { ref blocks, block_width, block_height } => Sums::Exact {
+                blocks: orientation.transform_grid(blocks, size, size),
+                block_width: if swap { block_height } else { block_width },
+                block_height: if swap { block_width } else { block_height },
+            },
+            Sums::Float { ref blocks, block_width, block_height } => Sums::Float {
+                blocks: orientation.transform_grid(blocks, size, size),
+                block_width: if swap { block_height } else { block_width },
+                block_height: if swap { block_width } else { block_height },
+            },
+        };
+
+        BlockSums { sums, .. *self }
+    }
+}
+
+// The sums of the default method are kept as they always were, so stored hashes still match;
+// `reference_block_sums()` has the corrected ones.
+fn blockhash_slow<P: PixelSums>(pixels: &P, size: u32) -> Sums {
+    let mut blocks = vec![0f64; (size * size) as usize];
+
+    let (width, height) = pixels.dimensions();
     
     // Block dimensions, in pixels
     let (block_width, block_height) = (width as f64 / size as f64, height as f64 / size as f64);
 
     let idx = |x, y| (y * size + x) as usize;
 
-    img.foreach_pixel(|x, y, px| {
-        let px_sum = sum_px(px) as f64;
-
+    pixels.foreach_px_sum(|x, y, px_sum| {
         let (x, y) = (x as f64, y as f64);
 
         let block_x = x / block_width;
         let block_y = y / block_height;
 
+        // Meant as `(x + 1.) % block_width`; as written, each pixel counts towards one block.
         let x_mod = x + 1. % block_width;
         let y_mod = y + 1. % block_height;
 
@@ -109,29 +341,235 @@ fn blockhash_slow<I: HashImage>(img: &I, size: u32) -> BitVec {
         blocks[idx(block_right, block_bottom)] += px_sum * weight_right * weight_bottom;
     });
 
-    
-    gen_hash!(I, f64, blocks, size, block_width, block_height,
-        |l: f64, r: f64| (l - r).abs() < FLOAT_EQ_MARGIN)
+    Sums::Float { blocks, block_width, block_height }
 }
 
-fn blockhash_fast<I: HashImage>(img: &I, size: u32) -> BitVec {
+fn blockhash_fast<P: PixelSums>(pixels: &P, size: u32) -> Sums {
     let mut blocks = vec![0u32; (size * size) as usize];
-    let (width, height) = img.dimensions();
+    let (width, height) = pixels.dimensions();
 
     let (block_width, block_height) = (width / size, height / size);
 
+    // Rows of blocks have always been `block_width` pixels tall here. Only images at least as
+    // wide as they are tall got a hash that way (taller ones ran off the end of `blocks`), so
+    // those keep it and taller ones use the block height.
+    let row_height = if block_width >= block_height { block_width } else { block_height };
+
     let idx = |x, y| (y * size + x) as usize;
 
-    img.foreach_pixel(|x, y, px| { 
-        let px_sum = sum_px(px);
+    if pixels.is_wide() {
+        let mut blocks = vec![0f64; (size * size) as usize];
 
+        pixels.foreach_px_sum(|x, y, px_sum| {
+            let block_x = x / block_width;
+            let block_y = y / row_height;
+
+            blocks[idx(block_x, block_y)] += px_sum;
+        });
+
+        return Sums::Float { blocks, block_width: block_width as f64, block_height: block_height as f64 };
+    }
+
+    pixels.foreach_sum(|x, y, px_sum| { 
         let block_x = x / block_width;
-        let block_y = y / block_width;
+        let block_y = y / row_height;
 
         blocks[idx(block_x, block_y)] += px_sum;
     });
 
-    gen_hash!(I, u32, blocks, size, block_width, block_height, |l, r| l == r)    
+    Sums::Exact { blocks, block_width, block_height }
+}
+
+/// The pixels the default method sums: an image, or the sums of its pixels turned.
+trait PixelSums {
+    fn dimensions(&self) -> (u32, u32);
+
+    fn is_wide(&self) -> bool;
+
+    /// Call `iter_fn` with `sum_px()` of each pixel, row by row; only if not `is_wide()`.
+    fn foreach_sum<F: FnMut(u32, u32, u32)>(&self, iter_fn: F);
+
+    /// Call `iter_fn` with the sum of each pixel as `foreach_px_sum()` gives it, row by row.
+    fn foreach_px_sum<F: FnMut(u32, u32, f64)>(&self, iter_fn: F);
+}
+
+struct Image<'a, I: 'a>(&'a I);
+
+impl<'a, I: HashImage> PixelSums for Image<'a, I> {
+    fn dimensions(&self) -> (u32, u32) {
+        self.0.dimensions()
+    }
+
+    fn is_wide(&self) -> bool {
+        self.0.is_wide()
+    }
+
+    fn foreach_sum<F: FnMut(u32, u32, u32)>(&self, mut iter_fn: F) {
+        self.0.foreach_pixel(|x, y, px| iter_fn(x, y, sum_px(px)));
+    }
+
+    fn foreach_px_sum<F: FnMut(u32, u32, f64)>(&self, iter_fn: F) {
+        foreach_px_sum(self.0, iter_fn);
+    }
+}
+
+/// The sums of the pixels of an image, at the precision of the image, kept to be turned.
+struct SumGrid {
+    width: u32,
+    height: u32,
+    sums: GridSums,
+}
+
+enum GridSums {
+    Exact(Vec<u32>),
+    Wide(Vec<f64>),
+}
+
+impl SumGrid {
+    fn new<I: HashImage>(img: &I) -> Self {
+        let (width, height) = img.dimensions();
+        let idx = |x, y| (y * width + x) as usize;
+        let len = width as usize * height as usize;
+
+        let sums = if img.is_wide() {
+            let mut sums = vec![0f64; len];
+            foreach_px_sum(img, |x, y, px_sum| sums[idx(x, y)] = px_sum);
+            GridSums::Wide(sums)
+        } else {
+            let mut sums = vec![0u32; len];
+            img.foreach_pixel(|x, y, px| sums[idx(x, y)] = sum_px(px));
+            GridSums::Exact(sums)
+        };
+
+        SumGrid { width, height, sums }
+    }
+
+    fn turned(&self, orientation: Orientation) -> Self {
+        let (width, height) = (self.width as usize, self.height as usize);
+
+        let sums = match self.sums {
+            GridSums::Exact(ref sums) => GridSums::Exact(orientation.transform_grid(sums, width, height)),
+            GridSums::Wide(ref sums) => GridSums::Wide(orientation.transform_grid(sums, width, height)),
+        };
+
+        if orientation.swaps_axes() {
+            SumGrid { width: self.height, height: self.width, sums }
+        } else {
+            SumGrid { width: self.width, height: self.height, sums }
+        }
+    }
+}
+
+impl PixelSums for SumGrid {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn is_wide(&self) -> bool {
+        match self.sums {
+            GridSums::Exact(_) => false,
+            GridSums::Wide(_) => true,
+        }
+    }
+
+    fn foreach_sum<F: FnMut(u32, u32, u32)>(&self, mut iter_fn: F) {
+        match self.sums {
+            GridSums::Exact(ref sums) => for (i, &px_sum) in sums.iter().enumerate() {
+                iter_fn(i as u32 % self.width, i as u32 / self.width, px_sum);
+            },
+            GridSums::Wide(_) => panic!("`foreach_sum()` called on a wide image"),
+        }
+    }
+
+    fn foreach_px_sum<F: FnMut(u32, u32, f64)>(&self, mut iter_fn: F) {
+        let pos = |i: usize| (i as u32 % self.width, i as u32 / self.width);
+
+        match self.sums {
+            GridSums::Exact(ref sums) => for (i, &px_sum) in sums.iter().enumerate() {
+                let (x, y) = pos(i);
+                iter_fn(x, y, px_sum as f64);
+            },
+            GridSums::Wide(ref sums) => for (i, &px_sum) in sums.iter().enumerate() {
+                let (x, y) = pos(i);
+                iter_fn(x, y, px_sum);
+            },
+        }
+    }
+}
+
+/// The blocks that pixel `pos` of an axis `len` pixels long falls in, where blocks are
+/// `block_len` pixels long, and the fraction of the pixel in each: `(first, second,
+/// first_weight, second_weight)`.
+///
+/// A pixel only straddles two blocks if a block boundary falls inside it.
+fn block_span(pos: u32, len: u32, block_len: f64) -> (u32, u32, f64, f64) {
+    let end = (pos + 1) as f64 % block_len;
+    let (end_frac, end_int) = (end.fract(), end.trunc());
+
+    let first = floor_div(pos as f64, block_len) as u32;
+
+    // `end_int` is 0 on block boundaries, and at the last pixel, past which there is no block.
+    let second = if end_int > 0. || pos + 1 == len {
+        first
+    } else {
+        -floor_div(-(pos as f64), block_len) as u32
+    };
+
+    (first, second, 1. - end_frac, end_frac)
+}
+
+/// Floor division of floats, rounding exactly as Python's `//` does.
+fn floor_div(x: f64, y: f64) -> f64 {
+    let modulo = x % y;
+    let mut div = (x - modulo) / y;
+
+    if modulo != 0. && (y < 0.) != (modulo < 0.) {
+        div -= 1.;
+    }
+
+    if div == 0. {
+        return 0f64.copysign(x / y);
+    }
+
+    let floor = div.floor();
+    if div - floor > 0.5 { floor + 1. } else { floor }
+}
+
+/// The bits for reference block sums: above the median of their quarter of the blocks, or
+/// equal to it when it is more than half of the maximum.
+fn reference_bits(blocks: &[f64], pixels_per_block: f64) -> BitVec {
+    let half_block_value = pixels_per_block * 256. * 3. / 2.;
+
+    blocks.chunks(blocks.len() / 4)
+        .flat_map(|band| {
+            let median = reference_median(band);
+            band.iter().map(move |&block| block > median || ((block - median).abs() < 1. && median > half_block_value))
+        })
+        .collect()
+}
+
+/// The median, averaging the middle two values of an even number of them.
+fn reference_median(data: &[f64]) -> f64 {
+    let mut sorted = data.to_owned();
+    sorted.sort_by(|left, right| left.total_cmp(right));
+
+    let len = sorted.len();
+
+    if len % 2 == 0 {
+        (sorted[len / 2 - 1] + sorted[len / 2]) / 2.
+    } else {
+        sorted[len / 2]
+    }
+}
+
+/// Call `iter_fn` with the sum of the color channels of each pixel, on the 0-255 scale of
+/// `sum_px()` but at the full precision of the image.
+fn foreach_px_sum<I: HashImage, F: FnMut(u32, u32, f64)>(img: &I, mut iter_fn: F) {
+    if img.is_wide() {
+        img.foreach_pixel_normalized(|x, y, px| iter_fn(x, y, sum_px_normalized(px) * 255.));
+    } else {
+        img.foreach_pixel(|x, y, px| iter_fn(x, y, sum_px(px) as f64));
+    }
 }
 
 #[inline(always)]
@@ -148,7 +586,20 @@ fn sum_px(px: &[u8]) -> u32 {
     }
 }
 
+/// `sum_px()` for channel values in `[0, 1]`.
+#[inline(always)]
+fn sum_px_normalized(px: &[f32]) -> f64 {
+    match px.len() {
+        4 => if px[3] == 0. { 3. } else { sum_px_normalized(&px[..3]) },
+        3 => px[0] as f64 + px[1] as f64 + px[2] as f64,
+        2 => if px[1] == 0. { 1. } else { px[0] as f64 },
+        1 => px[0] as f64,
+        _ => panic!("Channel count was different than actual pixel size"),
+    }
+}
+
 // Get the next multiple of 4 up from x, or x if x is a multiple of 4
+#[allow(clippy::precedence)]
 fn next_multiple_of_4(x: u32) -> u32 {
     x + 3 & !3
 }
@@ -182,6 +633,7 @@ fn qselect_inplace<T: PartialOrd>(data: &mut [T], k: usize) -> &mut T {
     }
 }
 
+#[allow(clippy::op_ref)]
 fn partition<T: PartialOrd>(data: &mut [T]) -> usize {
     let len = data.len();
 
@@ -218,9 +670,82 @@ pub fn median_of_3<T: PartialOrd>(mut x: T, mut y: T, mut z: T) -> T {
         mem::swap(&mut x, &mut z);
     }
 
-    if x > z {
+    if y > z {
         mem::swap(&mut y, &mut z);
     }
 
     y
 }
+
+#[cfg(test)]
+mod test {
+    use image::{GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    use {Hasher, HashImage, HashType, ImageHash};
+
+    fn rgb(x: u32, y: u32) -> Rgb<u8> {
+        Rgb([((x * 5 + y * 3) % 256) as u8, ((x * y) % 256) as u8, (((x ^ y) * 2) % 256) as u8])
+    }
+
+    fn rgba(x: u32, y: u32) -> Rgba<u8> {
+        let Rgb([r, g, b]) = rgb(x, y);
+        Rgba([r, g, b, if (x + y) % 11 == 0 { 0 } else { 255 }])
+    }
+
+    fn hex<I: HashImage>(img: &I, bits: u32, method: BlockhashMethod) -> String {
+        let hash = Hasher::new(bits, HashType::Block).blockhash_method(method).hash(img);
+        hash.bitv.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Expected hashes from a transcription of blockhash-python's `blockhash()` (precise) and
+    /// `blockhash_even()` (quick), run on the same generated images.
+    #[test]
+    fn matches_reference() {
+        use self::BlockhashMethod::*;
+
+        assert_eq!(hex(&RgbImage::from_fn(64, 48, rgb), 16, Precise),
+                   "007803fb07f30ff909e713e337c125d60bcb5b855f035f037e017e037c077c0f");
+        assert_eq!(hex(&RgbImage::from_fn(50, 37, rgb), 8, Precise), "071f362e5c5c3c78");
+        assert_eq!(hex(&RgbaImage::from_fn(45, 32, rgba), 8, Precise), "071f1b172f0e562e");
+        assert_eq!(hex(&RgbImage::from_fn(50, 37, rgb), 8, Quick), "071f1f260e5e5c3c");
+        assert_eq!(hex(&RgbaImage::from_fn(45, 32, rgba), 16, Quick),
+                   "0433087f10ff21ff437f86f80ce119c7378f271c4e390c73086718ce399d53bb");
+
+        let gray = GrayImage::from_fn(30, 30, |x, y| Luma([((x * 9 + y * y) % 256) as u8]));
+        assert_eq!(hex(&gray, 8, Precise), "0f1e78e11ee13c8e");
+    }
+
+    fn legacy_hex<I: HashImage>(img: &I, bits: u32) -> String {
+        let hash = ImageHash::hash(img, bits, HashType::Block);
+        hash.bitv.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Expected hashes from the default method before `BlockhashMethod` was added, which must
+    /// not change.
+    #[test]
+    fn default_method_unchanged() {
+        assert_eq!(legacy_hex(&RgbImage::from_fn(64, 48, rgb), 8), "1c3d593971e10000");
+        assert_eq!(legacy_hex(&RgbImage::from_fn(64, 48, rgb), 16),
+                   "007803f30ff11bef33e125c30bc35f8b5f017e037e037c0f0000000000000000");
+        assert_eq!(legacy_hex(&RgbImage::from_fn(48, 48, rgb), 8), "031f0e561c787870");
+        assert_eq!(legacy_hex(&RgbImage::from_fn(50, 37, rgb), 8), "031f066e5e0c7838");
+        assert_eq!(legacy_hex(&RgbaImage::from_fn(45, 32, rgba), 8), "070f1b162a565a2a");
+        assert_eq!(legacy_hex(&RgbaImage::from_fn(37, 64, rgba), 8), "0b1b0b535e58f0f0");
+        assert_eq!(legacy_hex(&RgbImage::from_fn(7, 5, rgb), 8), "0e1e00fefe00fe00");
+
+        let gray = GrayImage::from_fn(30, 30, |x, y| Luma([((x * 9 + y * y) % 256) as u8]));
+        assert_eq!(legacy_hex(&gray, 8), "0e2ef8c00ee36e0e");
+
+        // Used to run off the end of the blocks.
+        assert_eq!(ImageHash::hash(&RgbImage::from_fn(48, 64, rgb), 8, HashType::Block).bitv.len(), 64);
+    }
+
+    #[test]
+    fn median_of_3_is_median() {
+        for &(x, y, z) in &[(1, 2, 3), (1, 3, 2), (2, 1, 3), (2, 3, 1), (3, 1, 2), (3, 2, 1)] {
+            assert_eq!(median_of_3(x, y, z), 2);
+        }
+    }
+}
@@ -0,0 +1,488 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! A persistent cache of image hashes for repeated scans of the same files.
+//!
+//! Entries are keyed on the file path and are only returned while the file's size and
+//! modification time (or, optionally, a digest of its contents) are unchanged and the
+//! `Hasher::fingerprint()` matches the one the entry was hashed with, so changing the
+//! algorithm or hash size invalidates them.
+//!
+//! The cache file is plain text, one entry per line, and is only written by `HashCache::save()`.
+//!
+//! ```rust,no_run
+//! # extern crate image;
+//! # extern crate img_hash;
+//! use img_hash::{Hasher, HashType};
+//! use img_hash::cache::HashCache;
+//!
+//! # fn main() {
+//! let hasher = Hasher::new(8, HashType::Gradient);
+//! let mut cache = HashCache::open("hashes.cache").unwrap();
+//!
+//! // Only decodes and hashes the image if it's not in the cache or has changed.
+//! let hash = cache.get_or_hash(&hasher, "photo.jpg", |path| image::open(path)).unwrap();
+//!
+//! cache.save().unwrap();
+//! # }
+//! ```
+use serialize::base64::{FromBase64, ToBase64, STANDARD};
+
+use bit_vec::BitVec;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use {Hasher, HashImage, HashType, ImageHash};
+
+const HEADER: &str = "# img_hash cache v1";
+
+/// How the cache decides whether a file has changed since it was hashed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validation {
+    /// Compare the file's size and modification time. Cheap, and the default.
+    Metadata,
+    /// Compare the file's size and a digest of its contents.
+    ///
+    /// Survives copies and `touch` but has to read every file on every lookup.
+    ContentDigest,
+}
+
+/// What the cache knows about a file's contents when it was hashed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The modification time as seconds and nanoseconds since the Unix epoch,
+    /// with `Validation::Metadata`.
+    pub mtime: Option<(u64, u32)>,
+    /// A 64-bit FNV-1a digest of the file's contents, with `Validation::ContentDigest`.
+    pub digest: Option<u64>,
+}
+
+impl FileStamp {
+    /// Read the stamp of the file at `path`.
+    pub fn read(path: &Path, validation: Validation) -> io::Result<FileStamp> {
+        let meta = fs::metadata(path)?;
+
+        let mut stamp = FileStamp {
+            size: meta.len(),
+            mtime: None,
+            digest: None,
+        };
+
+        match validation {
+            Validation::Metadata => {
+                stamp.mtime = meta.modified().ok()
+                    .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                    .map(|mtime| (mtime.as_secs(), mtime.subsec_nanos()));
+            },
+            Validation::ContentDigest => stamp.digest = Some(digest_file(path)?),
+        }
+
+        Ok(stamp)
+    }
+
+    fn encode(&self) -> String {
+        match (self.mtime, self.digest) {
+            (Some((secs, nanos)), _) => format!("m{}.{:09}", secs, nanos),
+            (None, Some(digest)) => format!("d{:016x}", digest),
+            (None, None) => "-".into(),
+        }
+    }
+
+    fn decode(size: u64, encoded: &str) -> Option<FileStamp> {
+        let mut stamp = FileStamp { size, mtime: None, digest: None };
+
+        if let Some(mtime) = encoded.strip_prefix('m') {
+            let mut parts = mtime.splitn(2, '.');
+            let secs = parts.next()?.parse().ok()?;
+            let nanos = parts.next()?.parse().ok()?;
+            stamp.mtime = Some((secs, nanos));
+        } else if let Some(digest) = encoded.strip_prefix('d') {
+            stamp.digest = Some(u64::from_str_radix(digest, 16).ok()?);
+        } else if encoded != "-" {
+            return None;
+        }
+
+        Some(stamp)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    fingerprint: String,
+    stamp: FileStamp,
+    hash: ImageHash,
+}
+
+/// A cache mapping file paths to their hashes, optionally backed by a file.
+///
+/// Paths are used exactly as given; use absolute paths if the working directory may change
+/// between runs.
+///
+/// Each path holds one entry, so hashing a file with a differently configured `Hasher`
+/// replaces the entry instead of adding a second one.
+#[derive(Clone, Debug)]
+pub struct HashCache {
+    file: Option<PathBuf>,
+    validation: Validation,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl HashCache {
+    /// Create an empty in-memory cache. `save()` will do nothing.
+    pub fn new() -> Self {
+        HashCache {
+            file: None,
+            validation: Validation::Metadata,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Open the cache stored at `file`, or start an empty one to be saved there
+    /// if it does not exist yet.
+    ///
+    /// Lines that cannot be parsed are skipped, as is the whole file if it was written
+    /// by an incompatible version.
+    pub fn open<P: AsRef<Path>>(file: P) -> io::Result<Self> {
+        let file = file.as_ref();
+        let mut cache = HashCache { file: Some(file.to_owned()), .. HashCache::new() };
+
+        let reader = match File::open(file) {
+            Ok(f) => BufReader::new(f),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e),
+        };
+
+        let mut lines = reader.lines();
+
+        match lines.next().transpose()? {
+            Some(ref header) if header == HEADER => (),
+            _ => return Ok(cache),
+        }
+
+        for line in lines {
+            if let Some((path, entry)) = parse_line(&line?) {
+                cache.entries.insert(path, entry);
+            }
+        }
+
+        Ok(cache)
+    }
+
+    /// Set how the cache checks whether files have changed. Defaults to `Validation::Metadata`.
+    ///
+    /// Entries stamped with a different validation are treated as stale.
+    pub fn validation(self, validation: Validation) -> Self {
+        HashCache { validation, .. self }
+    }
+
+    /// Read the stamp of `path` with this cache's validation.
+    pub fn stamp(&self, path: &Path) -> io::Result<FileStamp> {
+        FileStamp::read(path, self.validation)
+    }
+
+    /// Get the hash of `path` by `hasher` if it's cached and `stamp` is unchanged.
+    pub fn get(&self, hasher: &Hasher, path: &Path, stamp: &FileStamp) -> Option<&ImageHash> {
+        self.entries.get(path)
+            .filter(|entry| entry.stamp == *stamp && entry.fingerprint == hasher.fingerprint())
+            .map(|entry| &entry.hash)
+    }
+
+    /// Cache `hash`, the hash of `path` by `hasher` when the file had the given stamp.
+    pub fn insert(&mut self, hasher: &Hasher, path: &Path, stamp: FileStamp, hash: ImageHash) {
+        self.entries.insert(path.to_owned(), Entry {
+            fingerprint: hasher.fingerprint(),
+            stamp,
+            hash,
+        });
+    }
+
+    /// Get the cached hash of `path`, or load it with `load`, hash it with `hasher` and cache it.
+    pub fn get_or_hash<P, I, E, F>(&mut self, hasher: &Hasher, path: P, load: F) -> Result<ImageHash, E>
+    where P: AsRef<Path>, I: HashImage, E: From<io::Error>, F: FnOnce(&Path) -> Result<I, E> {
+        let path = path.as_ref();
+        let hashed = self.get_or_load(hasher, path, load);
+        self.insert_hashed(hasher, path, hashed)
+    }
+
+    /// The first half of `get_or_hash()`, which only needs to read the cache: the cached hash of
+    /// `path`, or the hash of the file loaded with `load` and its stamp to cache it with.
+    pub(crate) fn get_or_load<I, E, F>(&self, hasher: &Hasher, path: &Path, load: F) -> Hashed<E>
+    where I: HashImage, E: From<io::Error>, F: FnOnce(&Path) -> Result<I, E> {
+        let stamp = self.stamp(path)?;
+
+        if let Some(hash) = self.get(hasher, path, &stamp) {
+            return Ok((hash.clone(), None));
+        }
+
+        let hash = hasher.hash(&load(path)?);
+        Ok((hash, Some(stamp)))
+    }
+
+    /// The second half of `get_or_hash()`: cache the hash from `get_or_load()` if it's new.
+    pub(crate) fn insert_hashed<E>(&mut self, hasher: &Hasher, path: &Path, hashed: Hashed<E>)
+        -> Result<ImageHash, E> {
+        hashed.map(|(hash, stamp)| {
+            if let Some(stamp) = stamp {
+                self.insert(hasher, path, stamp, hash.clone());
+            }
+
+            hash
+        })
+    }
+
+    /// Remove the entries for files that no longer exist.
+    pub fn prune_missing(&mut self) {
+        self.entries.retain(|path, _| path.exists());
+    }
+
+    /// The number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// `true` if the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the cache back to the file it was opened from, replacing it.
+    ///
+    /// Entries are written sorted by path, so saving an unchanged cache writes the same file.
+    /// Entries whose paths are not valid UTF-8 or contain line breaks are not saved.
+    pub fn save(&self) -> io::Result<()> {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return Ok(()),
+        };
+
+        // Write next to the original and rename over it so a crash can't truncate the cache.
+        let mut tmp_name = file.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp = PathBuf::from(tmp_name);
+
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            writeln!(out, "{}", HEADER)?;
+
+            let mut entries: Vec<_> = self.entries.iter().collect();
+            entries.sort_by(|l, r| l.0.cmp(r.0));
+
+            for (path, entry) in entries {
+                let path = match path.to_str() {
+                    Some(path) if !path.contains(['\n', '\r']) => path,
+                    _ => continue,
+                };
+
+                let mut bytes = entry.hash.bitv.to_bytes();
+                bytes.insert(0, entry.hash.hash_type.to_byte());
+
+                writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}",
+                         entry.fingerprint, entry.hash.bitv.len(), entry.stamp.size,
+                         entry.stamp.encode(), bytes.to_base64(STANDARD), path)?;
+            }
+
+            out.flush()?;
+        }
+
+        fs::rename(&tmp, file)
+    }
+}
+
+/// A hash from `HashCache::get_or_load()`, with the stamp of its file if it wasn't cached.
+pub(crate) type Hashed<E> = Result<(ImageHash, Option<FileStamp>), E>;
+
+impl Default for HashCache {
+    fn default() -> Self {
+        HashCache::new()
+    }
+}
+
+/// Hash the files at `paths` with `hasher`, returning the results in the same order.
+///
+/// Files whose hashes are in `cache` and unchanged are not loaded at all; the others are loaded
+/// with `load`, hashed and added to `cache`. Call `HashCache::save()` afterwards to keep the new
+/// entries.
+///
+/// With the `rayon` feature, `par::hash_files_cached()` does the same in parallel.
+pub fn hash_files_cached<P, I, E, F>(paths: &[P], hasher: &Hasher, cache: &mut HashCache, load: F)
+    -> Vec<Result<ImageHash, E>>
+where P: AsRef<Path>, I: HashImage, E: From<io::Error>, F: Fn(&Path) -> Result<I, E> {
+    paths.iter().map(|path| cache.get_or_hash(hasher, path, &load)).collect()
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, Entry)> {
+    let mut fields = line.splitn(6, '\t');
+
+    let fingerprint = fields.next()?.to_owned();
+    let bits: usize = fields.next()?.parse().ok()?;
+    let size = fields.next()?.parse().ok()?;
+    let stamp = FileStamp::decode(size, fields.next()?)?;
+    let mut bytes = fields.next()?.from_base64().ok()?;
+    let path = PathBuf::from(fields.next()?);
+
+    if bytes.is_empty() {
+        return None;
+    }
+
+    let hash_type = HashType::try_from_byte(bytes.remove(0))?;
+
+    // `BitVec::from_bytes()` pads to a whole number of bytes.
+    let mut bitv = BitVec::from_bytes(&bytes);
+
+    if bits > bitv.len() {
+        return None;
+    }
+
+    bitv.truncate(bits);
+
+    Some((path, Entry {
+        fingerprint,
+        stamp,
+        hash: ImageHash { bitv, hash_type },
+    }))
+}
+
+/// 64-bit FNV-1a; not cryptographic, but stable and good enough to detect changed files.
+fn digest_file(path: &Path) -> io::Result<u64> {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; 64 * 1024];
+    let mut digest = OFFSET_BASIS;
+
+    loop {
+        let read = match file.read(&mut buf) {
+            Ok(0) => return Ok(digest),
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for &byte in &buf[..read] {
+            digest ^= byte as u64;
+            digest = digest.wrapping_mul(PRIME);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+
+    fn test_hasher() -> Hasher {
+        Hasher::new(9, HashType::Mean)
+    }
+
+    fn test_hash() -> ImageHash {
+        ImageHash {
+            bitv: BitVec::from_fn(81, |i| i % 3 == 0),
+            hash_type: HashType::Mean,
+        }
+    }
+
+    #[test]
+    fn round_trip_and_invalidation() {
+        let dir = env::temp_dir().join(format!("img_hash_cache_test_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let image_path = dir.join("image.bin");
+        let cache_path = dir.join("hashes.cache");
+        fs::write(&image_path, b"not really an image").unwrap();
+
+        let hasher = test_hasher();
+
+        let mut cache = HashCache::open(&cache_path).unwrap();
+        let stamp = cache.stamp(&image_path).unwrap();
+        cache.insert(&hasher, &image_path, stamp, test_hash());
+        cache.save().unwrap();
+
+        let cache = HashCache::open(&cache_path).unwrap();
+        let stamp = cache.stamp(&image_path).unwrap();
+        assert_eq!(cache.get(&hasher, &image_path, &stamp), Some(&test_hash()));
+
+        // A different configuration must not see the entry.
+        assert_eq!(cache.get(&Hasher::new(8, HashType::Mean), &image_path, &stamp), None);
+        assert_eq!(cache.get(&Hasher::new(9, HashType::DCT), &image_path, &stamp), None);
+
+        // Neither must a changed file.
+        fs::write(&image_path, b"a different, longer image").unwrap();
+        let stamp = cache.stamp(&image_path).unwrap();
+        assert_eq!(cache.get(&hasher, &image_path, &stamp), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_sorted_by_path() {
+        let dir = env::temp_dir().join(format!("img_hash_cache_sorted_test_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let cache_path = dir.join("hashes.cache");
+        let stamp = FileStamp { size: 1, mtime: Some((5, 0)), digest: None };
+
+        let mut cache = HashCache::open(&cache_path).unwrap();
+        for name in &["d", "b", "e", "a", "c", "f"] {
+            cache.insert(&test_hasher(), &dir.join(name), stamp.clone(), test_hash());
+        }
+        cache.save().unwrap();
+        let saved = fs::read_to_string(&cache_path).unwrap();
+
+        let paths: Vec<_> = saved.lines().skip(1).map(|line| line.rsplit('\t').next().unwrap()).collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!((paths.len(), &paths), (6, &sorted));
+
+        HashCache::open(&cache_path).unwrap().save().unwrap();
+        assert_eq!(fs::read_to_string(&cache_path).unwrap(), saved);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hash_files_only_loads_changed() {
+        use image::{GrayImage, Luma};
+        use std::cell::Cell;
+
+        let dir = env::temp_dir().join(format!("img_hash_cache_files_test_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let paths = [dir.join("a.bin"), dir.join("b.bin"), dir.join("missing.bin")];
+        fs::write(&paths[0], b"a").unwrap();
+        fs::write(&paths[1], b"b").unwrap();
+
+        let loads = Cell::new(0);
+        let load = |path: &Path| {
+            loads.set(loads.get() + 1);
+            let shade = fs::read(path)?[0];
+            Ok::<_, io::Error>(GrayImage::from_fn(16, 16, |x, _| Luma([if x < 8 { shade } else { 255 - shade }])))
+        };
+
+        let hasher = test_hasher();
+        let mut cache = HashCache::new();
+
+        let first = hash_files_cached(&paths, &hasher, &mut cache, load);
+        assert_eq!(loads.get(), 2);
+        assert!(first[2].is_err());
+        assert_eq!(cache.len(), 2);
+
+        let second = hash_files_cached(&paths[..2], &hasher, &mut cache, load);
+        assert_eq!(loads.get(), 2);
+        assert_eq!(second[0].as_ref().unwrap(), first[0].as_ref().unwrap());
+        assert_eq!(second[1].as_ref().unwrap(), first[1].as_ref().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! The values each algorithm decides the bits of a hash from. Plain hashes, quality measures,
+//! soft and quantized hashes and the hashes of each orientation all take their bits from here,
+//! so they agree.
+use bit_vec::BitVec;
+
+use block::{self, BlockSums, BlockhashMethod};
+
+use dct::dct_2d_with_matrix;
+
+use dihedral::Orientation;
+
+use gradient::{self, GradientDirections};
+
+use quality::{self, HashQuality};
+
+use super::{
+    dct_bits, dct_of_values, dct_values, prepare_image, DctCache, HashImage, HashType, HashValue, Values,
+    DCT_HASH_SIZE_MULTIPLIER,
+};
+
+/// What the bits of a hash of one plane are decided from.
+pub(crate) struct Cells {
+    hash_size: u32,
+    values: CellValues,
+}
+
+enum CellValues {
+    /// A `hash_size` square grid, and its mean that each value is compared against.
+    Mean { values: Values, threshold: f64 },
+    /// The sums of the pixels in each block.
+    Block(BlockSums),
+    /// A grid of `GradientDirections::grid_size()`, `width` values wide.
+    Gradient { values: Values, width: usize, directions: GradientDirections },
+    /// The grayscale values at the size the DCT is taken at, and its lowest coefficients.
+    Dct { values: Vec<f64>, coefs: Vec<f64> },
+}
+
+impl Cells {
+    /// The cells of `img` for `hash_type`.
+    pub fn new<I: HashImage>(hash_type: HashType, img: &I, hash_size: u32, dct_cache: &DctCache,
+                             blockhash: Option<BlockhashMethod>) -> Self {
+        use HashType::*;
+
+        let values = match hash_type {
+            Mean => {
+                let values = prepare_image(img, hash_size, hash_size);
+                let threshold = match values {
+                    Values::Bytes(ref values) => u8::mean(values),
+                    Values::Wide(ref values) => f32::mean(values),
+                };
+
+                CellValues::Mean { values, threshold }
+            },
+            Block => CellValues::Block(match blockhash {
+                Some(method) => block::reference_block_sums(img, hash_size, method),
+                None => block::block_sums(img, hash_size),
+            }),
+            Gradient | DoubleGradient | VerticalGradient | DiagonalGradient | MultiGradient(_) =>
+                return Self::gradient(hash_type, img, hash_size, false),
+            DCT | UserDCT(_) => {
+                let large_size = (hash_size * DCT_HASH_SIZE_MULTIPLIER) as usize;
+                let values = dct_values(img, hash_size);
+
+                let coefs = match hash_type {
+                    UserDCT(dct_2d_func) =>
+                        dct_of_values(&values, hash_size, |data, rowstride| dct_2d_func.call(data, rowstride)),
+                    _ => {
+                        let matrix = dct_cache.matrix(large_size);
+                        dct_of_values(&values, hash_size, |data, rowstride| dct_2d_with_matrix(data, rowstride, &matrix))
+                    },
+                };
+
+                CellValues::Dct { values, coefs }
+            },
+            __BackCompat => panic!("`HashType::__BackCompat` is not an actual hash algorithm"),
+        };
+
+        Cells { hash_size, values }
+    }
+
+    /// The cells of `img` resized with the width and height of the grid swapped, which turned
+    /// by 90° have the shape of the grid `new()` resizes to; `None` if that grid is square.
+    pub fn swapped<I: HashImage>(hash_type: HashType, img: &I, hash_size: u32) -> Option<Self> {
+        let (width, height) = hash_type.gradient_directions()?.grid_size(hash_size);
+
+        if width != height { Some(Self::gradient(hash_type, img, hash_size, true)) } else { None }
+    }
+
+    fn gradient<I: HashImage>(hash_type: HashType, img: &I, hash_size: u32, swap: bool) -> Self {
+        let directions = hash_type.gradient_directions().unwrap();
+        let (width, height) = directions.grid_size(hash_size);
+        let (width, height) = if swap { (height, width) } else { (width, height) };
+
+        Cells {
+            hash_size,
+            values: CellValues::Gradient {
+                values: prepare_image(img, width, height),
+                width: width as usize,
+                directions,
+            },
+        }
+    }
+
+    /// The bits of the hash, one per cell.
+    pub fn bits(&self) -> BitVec {
+        let hash_size = self.hash_size;
+
+        match self.values {
+            CellValues::Mean { values: Values::Bytes(ref values), threshold } => mean_bits(values, threshold),
+            CellValues::Mean { values: Values::Wide(ref values), threshold } => mean_bits(values, threshold),
+            CellValues::Block(ref sums) => sums.to_bits(),
+            CellValues::Gradient { values: Values::Bytes(ref values), directions, .. } =>
+                gradient::gradient_bits(values, hash_size, directions),
+            CellValues::Gradient { values: Values::Wide(ref values), directions, .. } =>
+                gradient::gradient_bits(values, hash_size, directions),
+            CellValues::Dct { ref coefs, .. } => dct_bits(coefs),
+        }
+    }
+
+    /// For each bit, the distance between the value it was decided from and its threshold, in
+    /// the units of `HashQuality::margin`.
+    pub fn margins(&self) -> Vec<f64> {
+        match self.values {
+            CellValues::Mean { values: Values::Bytes(ref values), threshold } => mean_margins(values, threshold),
+            CellValues::Mean { values: Values::Wide(ref values), threshold } => mean_margins(values, threshold),
+            CellValues::Block(ref sums) =>
+                sums.levels().into_iter().map(|(level, median)| (level - median).abs()).collect(),
+            CellValues::Gradient { ref values, directions, .. } =>
+                gradient::gradient_diffs(&unit_values(values), self.hash_size, directions),
+            CellValues::Dct { ref coefs, .. } => {
+                let large_size = (self.hash_size * DCT_HASH_SIZE_MULTIPLIER) as usize;
+
+                // A cosine of amplitude 0.5 over the whole grid, in both directions, has a
+                // coefficient of `large_size^2 / 4`.
+                let threshold = coefs.iter().sum::<f64>() / coefs.len() as f64;
+                let scale = (large_size * large_size) as f64 / 4.;
+
+                coefs.iter().map(|&coef| ((coef - threshold) / scale).abs()).collect()
+            },
+        }
+    }
+
+    /// How much information `bits`, decided from these cells with `margins`, carries.
+    pub fn quality(&self, bits: &BitVec, margins: &[f64]) -> HashQuality {
+        let (grid, width, margins) = match self.values {
+            CellValues::Mean { ref values, .. } => (unit_values(values), self.hash_size as usize, margins),
+            CellValues::Block(ref sums) => {
+                let grid: Vec<f64> = sums.levels().into_iter().map(|(level, _)| level).collect();
+
+                // Blockhash rounds the size up to a multiple of 4.
+                let width = (grid.len() as f64).sqrt().round() as usize;
+                (grid, width, margins)
+            },
+            CellValues::Gradient { ref values, width, .. } => (unit_values(values), width, margins),
+            // The DC term is far from the threshold for any image but a black one.
+            CellValues::Dct { ref values, .. } =>
+                (values.clone(), (self.hash_size * DCT_HASH_SIZE_MULTIPLIER) as usize, &margins[1 ..]),
+        };
+
+        HashQuality::new(bits, &grid, width, quality::mean_abs(margins.iter().cloned()))
+    }
+
+    /// These cells with the image turned to `orientation`.
+    ///
+    /// Turned by 90°, non-square gradient grids must come from `swapped()`. Block sums don't
+    /// turn with the image; see `block::dihedral_block_sums()`.
+    pub fn turned(&self, orientation: Orientation) -> Self {
+        let size = self.hash_size as usize;
+
+        let values = match self.values {
+            CellValues::Mean { ref values, threshold } => CellValues::Mean {
+                values: turn_values(values, orientation, size, size),
+                threshold,
+            },
+            CellValues::Block(_) => unreachable!("`block::dihedral_block_sums()` turns block sums"),
+            CellValues::Gradient { ref values, width, directions } => {
+                let height = match *values {
+                    Values::Bytes(ref values) => values.len(),
+                    Values::Wide(ref values) => values.len(),
+                } / width;
+
+                CellValues::Gradient {
+                    values: turn_values(values, orientation, width, height),
+                    width: if orientation.swaps_axes() { height } else { width },
+                    directions,
+                }
+            },
+            CellValues::Dct { ref values, ref coefs } => {
+                let large_size = size * DCT_HASH_SIZE_MULTIPLIER as usize;
+
+                CellValues::Dct {
+                    values: orientation.transform_grid(values, large_size, large_size),
+                    coefs: orientation.transform_dct(coefs, size),
+                }
+            },
+        };
+
+        Cells { hash_size: self.hash_size, values }
+    }
+}
+
+/// Whether each value is at least `threshold`, at the precision of the values.
+fn mean_bits<T: HashValue>(values: &[T], threshold: f64) -> BitVec {
+    values.iter().map(|x| x.to_f64() >= threshold).collect()
+}
+
+/// The distance of each value from `threshold`, a mean from `HashValue::mean()`, scaled to `[0, 1]`.
+fn mean_margins<T: HashValue>(values: &[T], threshold: f64) -> Vec<f64> {
+    let threshold = threshold / T::MAX;
+    values.iter().map(|&val| (val.to_unit() - threshold).abs()).collect()
+}
+
+fn unit_values(values: &Values) -> Vec<f64> {
+    match *values {
+        Values::Bytes(ref values) => values.iter().map(|&val| val.to_unit()).collect(),
+        Values::Wide(ref values) => values.iter().map(|&val| val.to_unit()).collect(),
+    }
+}
+
+fn turn_values(values: &Values, orientation: Orientation, width: usize, height: usize) -> Values {
+    match *values {
+        Values::Bytes(ref values) => Values::Bytes(orientation.transform_grid(values, width, height)),
+        Values::Wide(ref values) => Values::Wide(orientation.transform_grid(values, width, height)),
+    }
+}
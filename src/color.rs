@@ -0,0 +1,262 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Choosing how color images, and their transparency, are reduced to the grayscale planes the
+//! algorithms hash.
+use super::HashImage;
+
+use preprocess::GrayBuffer;
+
+use raw::LumaWeights;
+
+/// How a hasher converts color images to grayscale, set with `Hasher::grayscale()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GrayConversion {
+    /// Whatever `HashImage::grayscale()` does for the image type. The default, and the only
+    /// conversion that keeps the image type's own resize filter.
+    #[default]
+    Native,
+    /// A weighted sum of red, green and blue.
+    Luma(LumaWeights),
+    /// The plain average of red, green and blue.
+    Average,
+    /// A single channel.
+    Channel(ColorChannel),
+    /// The lightness `L*` of CIELAB, which tracks perceived brightness more closely than luma.
+    Lightness,
+}
+
+impl GrayConversion {
+    /// A short string identifying this conversion in `Hasher::fingerprint()`.
+    pub(crate) fn fingerprint(self) -> String {
+        match self {
+            GrayConversion::Native => "native".to_owned(),
+            GrayConversion::Luma(LumaWeights::Bt601) => "bt601".to_owned(),
+            GrayConversion::Luma(LumaWeights::Bt709) => "bt709".to_owned(),
+            GrayConversion::Average => "average".to_owned(),
+            GrayConversion::Channel(channel) => format!("channel{}", channel as u8),
+            GrayConversion::Lightness => "lightness".to_owned(),
+        }
+    }
+
+    fn convert(self, [r, g, b]: [f32; 3]) -> f32 {
+        match self {
+            GrayConversion::Luma(weights) => {
+                let [wr, wg, wb] = weights.coefficients();
+                r * wr + g * wg + b * wb
+            },
+            GrayConversion::Native => unreachable!("`gray_plane()` uses `HashImage::grayscale()`"),
+            GrayConversion::Average => (r + g + b) / 3.,
+            GrayConversion::Channel(channel) => [r, g, b][channel as usize],
+            GrayConversion::Lightness => lightness([r, g, b]),
+        }
+    }
+}
+
+/// One of the red, green and blue channels, for `GrayConversion::Channel`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorChannel {
+    /// Red.
+    Red = 0,
+    /// Green.
+    Green = 1,
+    /// Blue.
+    Blue = 2,
+}
+
+/// The planes a hasher hashes separately and concatenates, set with `Hasher::per_channel()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channels {
+    /// Red, green and blue.
+    Rgb,
+    /// Luma and the blue and red color differences, per JPEG (BT.601, full range).
+    YCbCr,
+}
+
+impl Channels {
+    /// A short string identifying these channels in `Hasher::fingerprint()`.
+    pub(crate) fn fingerprint(self) -> &'static str {
+        match self {
+            Channels::Rgb => "rgb",
+            Channels::YCbCr => "ycbcr",
+        }
+    }
+
+    fn convert(self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        match self {
+            Channels::Rgb => [r, g, b],
+            Channels::YCbCr => [
+                0.299 * r + 0.587 * g + 0.114 * b,
+                0.5 - 0.168_736 * r - 0.331_264 * g + 0.5 * b,
+                0.5 + 0.5 * r - 0.418_688 * g - 0.081_312 * b,
+            ],
+        }
+    }
+}
+
+/// The CIELAB lightness of an sRGB color, scaled to `[0, 1]`.
+fn lightness(rgb: [f32; 3]) -> f32 {
+    let linear = |c: f32| if c <= 0.040_45 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+    let [wr, wg, wb] = LumaWeights::Bt709.coefficients();
+    let y = wr * linear(rgb[0]) + wg * linear(rgb[1]) + wb * linear(rgb[2]);
+
+    let f = if y > 216. / 24389. { y.cbrt() } else { (24389. / 27. * y + 16.) / 116. };
+    (116. * f - 16.) / 100.
+}
+
+/// What a hasher does with the alpha channel of images, set with `Hasher::alpha()`.
+///
+/// Without a policy set, `HashType::Block` counts fully transparent pixels as white and every
+/// other algorithm ignores alpha, as `HashImage::grayscale()` drops it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AlphaPolicy {
+    /// Hash the color channels as they are, whatever their alpha.
+    Ignore,
+    /// Blend each pixel onto a background of this red, green and blue by its alpha, as
+    /// flattening the image would.
+    Composite([u8; 3]),
+    /// Replace fully transparent pixels with this red, green and blue, and ignore partial alpha.
+    /// `[255, 255, 255]` is what Blockhash does.
+    ReplaceTransparent([u8; 3]),
+}
+
+impl AlphaPolicy {
+    /// A short string identifying this policy in `Hasher::fingerprint()`.
+    pub(crate) fn fingerprint(self) -> String {
+        match self {
+            AlphaPolicy::Ignore => "ignore".to_owned(),
+            AlphaPolicy::Composite([r, g, b]) => format!("composite{:02x}{:02x}{:02x}", r, g, b),
+            AlphaPolicy::ReplaceTransparent([r, g, b]) => format!("replace{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+
+    fn apply(self, rgb: [f32; 3], alpha: f32) -> [f32; 3] {
+        let unit = |[r, g, b]: [u8; 3]| [r as f32 / 255., g as f32 / 255., b as f32 / 255.];
+
+        match self {
+            AlphaPolicy::Ignore => rgb,
+            AlphaPolicy::Composite(background) => {
+                let background = unit(background);
+                let blend = |i: usize| rgb[i] * alpha + background[i] * (1. - alpha);
+                [blend(0), blend(1), blend(2)]
+            },
+            AlphaPolicy::ReplaceTransparent(background) if alpha == 0. => unit(background),
+            AlphaPolicy::ReplaceTransparent(_) => rgb,
+        }
+    }
+}
+
+/// Get the red, green and blue of each pixel of `img`, in `[0, 1]` and packed by row, with
+/// `alpha` applied to images that have an alpha channel.
+fn rgb_pixels<I: HashImage>(img: &I, alpha: AlphaPolicy) -> (u32, u32, Vec<[f32; 3]>) {
+    let (width, height) = img.dimensions();
+    let mut pixels = vec![[0f32; 3]; width as usize * height as usize];
+
+    {
+        let mut set = |x: u32, y: u32, px: &[f32]| {
+            pixels[(y * width + x) as usize] = match px.len() {
+                1 => [px[0]; 3],
+                2 => alpha.apply([px[0]; 3], px[1]),
+                3 => [px[0], px[1], px[2]],
+                _ => alpha.apply([px[0], px[1], px[2]], px[3]),
+            };
+        };
+
+        if img.is_wide() {
+            img.foreach_pixel_normalized(|x, y, px| set(x, y, px));
+        } else {
+            let mut px_f32 = [0f32; 4];
+
+            img.foreach_pixel(|x, y, px| {
+                for (out, &channel) in px_f32.iter_mut().zip(px) {
+                    *out = channel as f32 / 255.;
+                }
+
+                set(x, y, &px_f32[..px.len()]);
+            });
+        }
+    }
+
+    (width, height, pixels)
+}
+
+/// Convert `img` to grayscale with `conversion`, applying `alpha` if set.
+///
+/// `GrayConversion::Native` can't see alpha, so with a policy set it is replaced by BT.709 luma,
+/// the weights the `image` crate uses.
+pub(crate) fn gray_plane<I: HashImage>(img: &I, conversion: GrayConversion, alpha: Option<AlphaPolicy>)
+    -> GrayBuffer {
+    let conversion = match (conversion, alpha) {
+        (GrayConversion::Native, None) => return GrayBuffer::from_image(img),
+        (GrayConversion::Native, Some(_)) => GrayConversion::Luma(LumaWeights::Bt709),
+        (conversion, _) => conversion,
+    };
+
+    let (width, height, pixels) = rgb_pixels(img, alpha.unwrap_or(AlphaPolicy::Ignore));
+    GrayBuffer::new(width, height, pixels.into_iter().map(|px| conversion.convert(px)).collect())
+}
+
+/// Split `img` into the three planes of `channels`, applying `alpha` if set.
+pub(crate) fn channel_planes<I: HashImage>(img: &I, channels: Channels, alpha: Option<AlphaPolicy>)
+    -> Vec<GrayBuffer> {
+    let (width, height, pixels) = rgb_pixels(img, alpha.unwrap_or(AlphaPolicy::Ignore));
+    let converted: Vec<[f32; 3]> = pixels.into_iter().map(|px| channels.convert(px)).collect();
+
+    (0 .. 3)
+        .map(|channel| GrayBuffer::new(width, height, converted.iter().map(|px| px[channel]).collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    use {Hasher, HashType, LumaWeights};
+
+    use super::*;
+
+    #[test]
+    fn per_channel_sees_color() {
+        // Red and blue swap places; the luma barely changes.
+        let img = RgbImage::from_fn(32, 32, |x, _| if x < 16 { Rgb([200, 0, 30]) } else { Rgb([30, 0, 200]) });
+        let swapped = RgbImage::from_fn(32, 32, |x, _| if x < 16 { Rgb([30, 0, 200]) } else { Rgb([200, 0, 30]) });
+
+        let gray = Hasher::new(8, HashType::Mean).grayscale(GrayConversion::Luma(LumaWeights::Bt601));
+        let color = Hasher::new(8, HashType::Mean).per_channel(Channels::Rgb);
+
+        assert_eq!(gray.fingerprint(), "mean:8:gray-bt601");
+        assert_eq!(color.fingerprint(), "mean:8:channels-rgb");
+
+        assert_eq!(color.hash(&img).bitv.len(), 3 * 64);
+        assert!(gray.hash(&img).dist(&gray.hash(&swapped)) < color.hash(&img).dist(&color.hash(&swapped)));
+
+        let red = Hasher::new(8, HashType::Mean).grayscale(GrayConversion::Channel(ColorChannel::Red));
+        assert_eq!(red.fingerprint(), "mean:8:gray-channel0");
+        assert_eq!(red.hash(&img).bitv, color.hash(&img).bitv.iter().take(64).collect());
+    }
+
+    #[test]
+    fn composite_matches_flattened() {
+        // A disc on a transparent background whose color channels happen to be black.
+        let inside = |x: u32, y: u32| (x as i32 - 20).pow(2) + (y as i32 - 16).pow(2) < 100;
+        let img = RgbaImage::from_fn(48, 40, |x, y| {
+            if inside(x, y) { Rgba([40, 90, 160, 255]) } else { Rgba([0, 0, 0, 0]) }
+        });
+        let flattened = RgbImage::from_fn(48, 40, |x, y| {
+            if inside(x, y) { Rgb([40, 90, 160]) } else { Rgb([255, 255, 255]) }
+        });
+
+        for &hash_type in &[HashType::Mean, HashType::Block, HashType::Gradient, HashType::DCT] {
+            let hasher = Hasher::new(8, hash_type).alpha(AlphaPolicy::Composite([255, 255, 255]));
+            assert_eq!(hasher.hash(&img), hasher.hash(&flattened), "{:?}", hash_type);
+        }
+
+        let ignore = Hasher::new(8, HashType::Mean).alpha(AlphaPolicy::Ignore);
+        assert_eq!(ignore.fingerprint(), "mean:8:alpha-ignore");
+        assert_ne!(ignore.hash(&img), ignore.hash(&flattened));
+    }
+}
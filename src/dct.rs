@@ -7,9 +7,10 @@
 // except according to those terms.
 use super::Columns;
 
-use std::cell::RefCell;
+use std::collections::HashMap;
 use std::f64::consts::{PI, SQRT_2};
 use std::ops::{Index, IndexMut};
+use std::sync::{Arc, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 struct ColumnsMut<'a, T: 'a> {
     data: &'a mut [T],
@@ -18,6 +19,7 @@ struct ColumnsMut<'a, T: 'a> {
 }
 
 impl<'a, T: 'a> ColumnsMut<'a, T> {
+    #[allow(clippy::redundant_field_names)]
     #[inline(always)]
     fn from_slice(data: &'a mut [T], rowstride: usize) -> Self {
         ColumnsMut {
@@ -30,6 +32,7 @@ impl<'a, T: 'a> ColumnsMut<'a, T> {
 
 impl<'a, T: 'a> Iterator for ColumnsMut<'a, T> {
     type Item = ColumnMut<'a, T>;
+    #[allow(clippy::redundant_field_names)]
     #[inline(always)]
     fn next(&mut self) -> Option<Self::Item> {
         if self.curr < self.rowstride {
@@ -65,15 +68,98 @@ impl<'a, T: 'a> IndexMut<usize> for ColumnMut<'a, T> {
     }
 }
 
-thread_local! {
-    static PRECOMPUTED_MATRIX: RefCell<Vec<f64>> = RefCell::new(Vec::new());
+/// A thread-safe cache of precomputed DCT matrices, keyed by size.
+///
+/// Cloning a `DctCache` is cheap and the clone shares its matrices with the original,
+/// so one cache can be handed to any number of `Hasher`s on any number of threads.
+///
+/// There is also a process-wide cache, `DctCache::global()`, which `ImageHash::hash()` uses.
+#[derive(Clone, Debug)]
+pub struct DctCache {
+    matrices: Arc<RwLock<HashMap<usize, Arc<Vec<f64>>>>>,
+    // Whether hashing stores the matrices it computes, or only uses ones already precomputed.
+    keep_computed: bool,
 }
 
-/// Precompute the DCT matrix for a given hash size and memoize it in thread-local
-/// storage.
-///
+impl Default for DctCache {
+    fn default() -> Self {
+        DctCache {
+            matrices: Arc::default(),
+            keep_computed: true,
+        }
+    }
+}
+
+impl DctCache {
+    /// Create a new, empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the process-wide cache.
+    ///
+    /// Unlike other caches, hashing with this one does not store the matrices it computes, as
+    /// they would stay around for the life of the process: it only holds those added with
+    /// `precompute_dct_matrix()` or `DctCache::precompute()`.
+    pub fn global() -> &'static DctCache {
+        static GLOBAL: OnceLock<DctCache> = OnceLock::new();
+        GLOBAL.get_or_init(|| DctCache { keep_computed: false, .. DctCache::new() })
+    }
+
+    /// Precompute the DCT matrix used by `HashType::DCT` at the given hash size.
+    ///
+    /// Matrices of other sizes already in the cache are kept.
+    pub fn precompute(&self, hash_size: u32) {
+        self.get_or_init((hash_size * ::DCT_HASH_SIZE_MULTIPLIER) as usize);
+    }
+
+    /// Remove all matrices from the cache.
+    pub fn clear(&self) {
+        self.write().clear();
+    }
+
+    /// Get the matrix of the exact given size if it's been computed already.
+    pub fn get(&self, size: usize) -> Option<Arc<Vec<f64>>> {
+        self.read().get(&size).cloned()
+    }
+
+    /// Get the matrix of the exact given size, computing and storing it on first use.
+    pub fn get_or_init(&self, size: usize) -> Arc<Vec<f64>> {
+        if let Some(matrix) = self.get(size) {
+            return matrix;
+        }
+
+        // Compute outside of the lock; if another thread beats us to it, keep theirs.
+        let matrix = Arc::new(precompute_matrix(size));
+        self.write().entry(size).or_insert(matrix).clone()
+    }
+
+    /// Get the matrix of the exact given size for hashing: as `get_or_init()`, except that the
+    /// global cache computes the matrices it doesn't have without keeping them.
+    pub(crate) fn matrix(&self, size: usize) -> Arc<Vec<f64>> {
+        if self.keep_computed {
+            self.get_or_init(size)
+        } else {
+            self.get(size).unwrap_or_else(|| Arc::new(precompute_matrix(size)))
+        }
+    }
+
+    // A poisoned lock can only mean a panic during a `HashMap` operation,
+    // which leaves nothing half-computed in the map itself.
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<usize, Arc<Vec<f64>>>> {
+        self.matrices.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<usize, Arc<Vec<f64>>>> {
+        self.matrices.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Precompute the DCT matrix for a given hash size and memoize it in the process-wide
+/// `DctCache::global()`.
 ///
-/// If a precomputed matrix was already stored, even of the same length, it will be overwritten.
+/// Matrices for other sizes are kept, so this can be called once for each hash size in use,
+/// from any thread; every thread will see the precomputed matrices.
 ///
 /// This can produce a significant runtime savings (an order of magnitude on the author's machine)
 /// when performing multiple hashing runs with the same hash size, as compared to not performing
@@ -84,56 +170,47 @@ thread_local! {
 /// the hash size multiplier applied by the DCT hash algorithm, so just pass the same
 /// hash size that you would to `ImageHash::hash()`.
 ///
-/// ## Note: Thread-Local
-/// Because this uses thread-local storage, this will need to be called on every thread
-/// that will be using the DCT hash for the runtime benefit. You can also
-/// have precomputed matrices of different sizes for each thread.
+/// A `Hasher` given its own cache with `Hasher::dct_cache()` computes matrices on first use
+/// instead, and does not need this step.
 pub fn precompute_dct_matrix(size: u32) {
-    // The DCT hash uses a hash size larger than the user provided, so we have to
-    // precompute a matrix of the right size
-    let size = size * ::DCT_HASH_SIZE_MULTIPLIER;
-    precomp_exact(size);
+    DctCache::global().precompute(size);
 }
 
 /// Precompute a DCT matrix of the exact given size.
+#[cfg(all(test, feature = "bench"))]
 pub fn precomp_exact(size: u32) {
-    PRECOMPUTED_MATRIX.with(|matrix| precompute_matrix(size as usize, &mut matrix.borrow_mut()));
+    DctCache::global().get_or_init(size as usize);
 }
 
-#[cfg(feature = "bench")]
+#[cfg(all(test, feature = "bench"))]
 pub fn clear_precomputed_matrix() {
-    PRECOMPUTED_MATRIX.with(|matrix| matrix.borrow_mut().clear());
+    DctCache::global().clear();
 }
 
-fn precompute_matrix(size: usize, matrix: &mut Vec<f64>) {
-    matrix.resize(size * size, 0.0);
+fn precompute_matrix(size: usize) -> Vec<f64> {
+    let mut matrix = vec![0.0; size * size];
 
     for i in 0 .. size {
         for j in 0 .. size {
             matrix[i * size + j] = (PI * i as f64 * (2 * j + 1) as f64 / (2 * size) as f64).cos();
         }
     }
-}
-
-fn with_precomputed_matrix<F>(size: usize, with_fn: F) -> bool
-where F: FnOnce(&[f64]) {
-    PRECOMPUTED_MATRIX.with(|matrix| {
-        let matrix = matrix.borrow();
 
-        if matrix.len() == size * size {
-            with_fn(&matrix);
-            true
-        } else {
-            false
-        }
-    })
+    matrix
 }
 
+/// Perform a 1D DCT, using the matrix from `DctCache::global()` if one of the right size
+/// was precomputed.
+#[cfg(all(test, feature = "bench"))]
 pub fn dct_1d<I: Index<usize, Output=f64> + ?Sized, O: IndexMut<usize, Output=f64> + ?Sized>(input: &I, output: &mut O, len: usize) {
-    if with_precomputed_matrix(len, |matrix| dct_1d_precomputed(input, output, len, matrix)) {
-        return;
+    match DctCache::global().get(len) {
+        Some(matrix) => dct_1d_precomputed(input, output, len, &matrix),
+        None => dct_1d_slow(input, output, len),
     }
+}
 
+fn dct_1d_slow<I, O>(input: &I, output: &mut O, len: usize)
+where I: Index<usize, Output=f64> + ?Sized, O: IndexMut<usize, Output=f64> + ?Sized {
     for i in 0 .. len {        
         let mut z = 0.0;
 
@@ -152,6 +229,7 @@ pub fn dct_1d<I: Index<usize, Output=f64> + ?Sized, O: IndexMut<usize, Output=f6
     } 
 }
 
+#[allow(clippy::multiple_bound_locations)]
 fn dct_1d_precomputed<I: ?Sized, O: ?Sized>(input: &I, output: &mut O, len: usize, matrix: &[f64])
 where I: Index<usize, Output=f64>, O: IndexMut<usize, Output=f64> {
     for i in 0 .. len {
@@ -175,22 +253,39 @@ where I: Index<usize, Output=f64>, O: IndexMut<usize, Output=f64> {
 ///
 /// Returns a vector of the same size packed in the same way.
 pub fn dct_2d(packed_2d: &[f64], rowstride: usize) -> Vec<f64> {
+    let matrix = DctCache::global().get(rowstride);
+    dct_2d_impl(packed_2d, rowstride, matrix.as_ref().map(|matrix| &matrix[..]))
+}
+
+/// As `dct_2d()`, but with a matrix from `DctCache` of size `rowstride`.
+pub fn dct_2d_with_matrix(packed_2d: &[f64], rowstride: usize, matrix: &[f64]) -> Vec<f64> {
+    assert_eq!(matrix.len(), rowstride * rowstride);
+
+    dct_2d_impl(packed_2d, rowstride, Some(matrix))
+}
+
+fn dct_2d_impl(packed_2d: &[f64], rowstride: usize, matrix: Option<&[f64]>) -> Vec<f64> {
     assert_eq!(packed_2d.len() % rowstride, 0);
 
-    let mut scratch = Vec::with_capacity(packed_2d.len() * 2);
-    unsafe { scratch.set_len(packed_2d.len() * 2); }
+    let mut scratch = vec![0.0; packed_2d.len() * 2];
 
     {
         let (col_pass, row_pass) = scratch.split_at_mut(packed_2d.len());
     
         for (row_in, row_out) in packed_2d.chunks(rowstride)
                 .zip(row_pass.chunks_mut(rowstride)) {                
-            dct_1d(row_in, row_out, rowstride);
+            match matrix {
+                Some(matrix) => dct_1d_precomputed(row_in, row_out, rowstride, matrix),
+                None => dct_1d_slow(row_in, row_out, rowstride),
+            }
         }
 
         for (col_in, mut col_out) in Columns::from_slice(row_pass, rowstride)
                 .zip(ColumnsMut::from_slice(col_pass, rowstride)) {
-            dct_1d(&col_in, &mut col_out, rowstride);
+            match matrix {
+                Some(matrix) => dct_1d_precomputed(&col_in, &mut col_out, rowstride, matrix),
+                None => dct_1d_slow(&col_in, &mut col_out, rowstride),
+            }
         }
     }
 
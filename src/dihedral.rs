@@ -0,0 +1,286 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Hashes of all eight orientations of an image (rotations by multiples of 90° and mirrors),
+//! computed from a single pass over the image.
+use bit_vec::BitVec;
+
+use block::{self, BlockSums, BlockhashMethod};
+
+use cells::Cells;
+
+use super::{DctCache, HashImage, HashType};
+
+/// One of the eight ways to turn an image by a multiple of 90° and optionally mirror it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    /// As-is.
+    Identity,
+    /// Rotated 90° clockwise.
+    Rotate90,
+    /// Rotated 180°.
+    Rotate180,
+    /// Rotated 270° clockwise (90° counter-clockwise).
+    Rotate270,
+    /// Mirrored left to right.
+    FlipHorizontal,
+    /// Mirrored top to bottom.
+    FlipVertical,
+    /// Mirrored along the diagonal from top left to bottom right.
+    Transpose,
+    /// Mirrored along the diagonal from top right to bottom left.
+    Transverse,
+}
+
+impl Orientation {
+    /// All eight orientations, in the order `DihedralHashes` keeps them.
+    pub const ALL: [Orientation; 8] = [
+        Orientation::Identity,
+        Orientation::Rotate90,
+        Orientation::Rotate180,
+        Orientation::Rotate270,
+        Orientation::FlipHorizontal,
+        Orientation::FlipVertical,
+        Orientation::Transpose,
+        Orientation::Transverse,
+    ];
+
+    /// Returns `true` if this orientation swaps the width and height of an image.
+    pub fn swaps_axes(self) -> bool {
+        self.parts().0
+    }
+
+    /// This orientation as a transpose, followed by a horizontal and vertical flip.
+    fn parts(self) -> (bool, bool, bool) {
+        use self::Orientation::*;
+
+        match self {
+            Identity => (false, false, false),
+            Rotate90 => (true, true, false),
+            Rotate180 => (false, true, true),
+            Rotate270 => (true, false, true),
+            FlipHorizontal => (false, true, false),
+            FlipVertical => (false, false, true),
+            Transpose => (true, false, false),
+            Transverse => (true, true, true),
+        }
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&o| o == self).unwrap()
+    }
+
+    /// Turn a grid of `width * height` values, packed by row, to this orientation.
+    pub(crate) fn transform_grid<T: Copy>(self, grid: &[T], width: usize, height: usize) -> Vec<T> {
+        let (transpose, flip_x, flip_y) = self.parts();
+        let (new_width, new_height) = if transpose { (height, width) } else { (width, height) };
+
+        let mut out = Vec::with_capacity(grid.len());
+
+        for y in 0 .. new_height {
+            let y = if flip_y { new_height - 1 - y } else { y };
+
+            for x in 0 .. new_width {
+                let x = if flip_x { new_width - 1 - x } else { x };
+                let (src_x, src_y) = if transpose { (y, x) } else { (x, y) };
+
+                out.push(grid[src_y * width + src_x]);
+            }
+        }
+
+        out
+    }
+
+    /// Turn a square grid of 2D DCT coefficients to this orientation.
+    ///
+    /// Mirroring the image negates the odd frequencies along that axis.
+    pub(crate) fn transform_dct(self, coefs: &[f64], size: usize) -> Vec<f64> {
+        let (transpose, flip_x, flip_y) = self.parts();
+
+        (0 .. size * size).map(|i| {
+            let (u, v) = (i % size, i / size);
+            let coef = if transpose { coefs[u * size + v] } else { coefs[i] };
+
+            if (flip_x && u % 2 == 1) != (flip_y && v % 2 == 1) { -coef } else { coef }
+        }).collect()
+    }
+}
+
+/// The hashes of an image in all eight orientations, from `Hasher::hash_dihedral()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DihedralHashes {
+    hashes: Vec<::ImageHash>,
+}
+
+impl DihedralHashes {
+    /// Get the hash of the image turned to `orientation`.
+    pub fn get(&self, orientation: Orientation) -> &::ImageHash {
+        &self.hashes[orientation.index()]
+    }
+
+    /// Iterate over all eight orientations and their hashes.
+    pub fn iter(&self) -> impl Iterator<Item = (Orientation, &::ImageHash)> {
+        Orientation::ALL.iter().cloned().zip(&self.hashes)
+    }
+
+    /// The orientation of this image closest to the image hashed as `other`, and their distance.
+    pub fn closest(&self, other: &::ImageHash) -> (Orientation, usize) {
+        self.iter()
+            .map(|(orientation, hash)| (orientation, hash.dist(other)))
+            .min_by_key(|&(_, dist)| dist)
+            .unwrap()
+    }
+
+    /// The smallest distance between `other` and any orientation of this image.
+    ///
+    /// `other` only needs to be hashed as-is; comparing it against the hashes of this image in
+    /// every orientation finds it whether or not it was rotated or mirrored.
+    pub fn dist_invariant(&self, other: &::ImageHash) -> usize {
+        self.closest(other).1
+    }
+
+    /// Join the hashes of several planes of an image, orientation by orientation.
+    pub(crate) fn concat<I: IntoIterator<Item = DihedralHashes>>(parts: I) -> DihedralHashes {
+        let mut parts = parts.into_iter();
+        let mut joined = parts.next().expect("no planes to join");
+
+        for part in parts {
+            for (hash, other) in joined.hashes.iter_mut().zip(part.hashes) {
+                hash.bitv.extend(other.bitv.iter());
+            }
+        }
+
+        joined
+    }
+}
+
+/// The hashes of `img` in each orientation.
+///
+/// Each orientation turns the cells `HashType::hash()` decides the bits from: `Mean` and `DCT`
+/// permute (or, for `DCT`, negate) them, and the gradients on non-square grids, such as
+/// `Gradient`, need the image resized a second time, with width and height swapped. `Block`
+/// permutes the block sums of square images whose blocks are whole pixels, and sums the blocks
+/// of each orientation of other images again.
+pub fn hash_dihedral<I: HashImage>(hash_type: HashType, img: &I, hash_size: u32, dct_cache: &DctCache,
+                                  blockhash: Option<BlockhashMethod>) -> DihedralHashes {
+    let bits: Vec<BitVec> = match hash_type {
+        HashType::Block =>
+            block::dihedral_block_sums(img, hash_size, blockhash).iter().map(BlockSums::to_bits).collect(),
+        _ => {
+            let upright = Cells::new(hash_type, img, hash_size, dct_cache, blockhash);
+            let swapped = Cells::swapped(hash_type, img, hash_size);
+
+            // Turned by 90°, the grid resized with width and height swapped has the right shape.
+            orientations(|o| match swapped {
+                Some(ref swapped) if o.swaps_axes() => swapped.turned(o),
+                _ => upright.turned(o),
+            }.bits())
+        },
+    };
+
+    DihedralHashes {
+        hashes: bits.into_iter().map(|bitv| ::ImageHash { bitv, hash_type }).collect(),
+    }
+}
+
+fn orientations<F: FnMut(Orientation) -> BitVec>(hash_fn: F) -> Vec<BitVec> {
+    Orientation::ALL.iter().cloned().map(hash_fn).collect()
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::{weak_rng, Rng};
+
+    use image::imageops;
+    use image::RgbaImage;
+
+    use super::*;
+
+    use {GradientDirections, Hasher};
+
+    fn turn(img: &RgbaImage, orientation: Orientation) -> RgbaImage {
+        use self::Orientation::*;
+
+        match orientation {
+            Identity => img.clone(),
+            Rotate90 => imageops::rotate90(img),
+            Rotate180 => imageops::rotate180(img),
+            Rotate270 => imageops::rotate270(img),
+            FlipHorizontal => imageops::flip_horizontal(img),
+            FlipVertical => imageops::flip_vertical(img),
+            Transpose => imageops::flip_horizontal(&imageops::rotate90(img)),
+            Transverse => imageops::flip_vertical(&imageops::rotate90(img)),
+        }
+    }
+
+    #[test]
+    fn matches_turned_images() {
+        // Sized so that resizing is a no-op and the hashes must match exactly.
+        for &(hash_type, img_size) in &[(HashType::Mean, 8), (HashType::Block, 8), (HashType::DCT, 32),
+                                        (HashType::DoubleGradient, 9), (HashType::DiagonalGradient, 9),
+                                        (HashType::MultiGradient(GradientDirections::all()), 9)] {
+            let mut buf = vec![0; img_size * img_size * 4];
+            weak_rng().fill_bytes(&mut buf);
+            let img = RgbaImage::from_raw(img_size as u32, img_size as u32, buf).unwrap();
+
+            let hasher = Hasher::new(8, hash_type);
+            let hashes = hasher.hash_dihedral(&img);
+
+            for &orientation in &Orientation::ALL {
+                let turned = hasher.hash(&turn(&img, orientation));
+
+                assert_eq!(*hashes.get(orientation), turned, "{:?} {:?}", hash_type, orientation);
+                assert_eq!(hashes.closest(&turned), (orientation, 0));
+            }
+        }
+
+        let img = RgbaImage::from_fn(40, 30, |x, y| ::image::Rgba([x as u8 * 6, y as u8 * 8, 0, 255]));
+        for &hash_type in &[HashType::Gradient, HashType::VerticalGradient] {
+            let hasher = Hasher::new(8, hash_type);
+            assert_eq!(*hasher.hash_dihedral(&img).get(Orientation::Identity), hasher.hash(&img));
+        }
+    }
+
+    #[test]
+    fn non_square_matches_turned_images() {
+        use block::BlockhashMethod::*;
+
+        let hash_types = [HashType::Mean, HashType::Block, HashType::DCT, HashType::Gradient,
+                          HashType::DoubleGradient, HashType::VerticalGradient, HashType::DiagonalGradient,
+                          HashType::MultiGradient(GradientDirections::all())];
+        let block_hashers = || [Quick, Precise].iter()
+            .map(|&method| Hasher::new(8, HashType::Block).blockhash_method(method))
+            .chain(Some(Hasher::new(8, HashType::Block)));
+
+        // Odd sizes, which nearest-neighbour resizing samples the same way turned, for every
+        // algorithm; even ones only for `Block`, which sums every pixel.
+        for &(width, height) in &[(37, 23), (23, 37), (40, 30), (18, 27), (50, 20)] {
+            let mut buf = vec![0; width as usize * height as usize * 4];
+            weak_rng().fill_bytes(&mut buf);
+            let img = RgbaImage::from_raw(width, height, buf).unwrap();
+
+            let hashers: Vec<Hasher> = if width % 2 == 1 && height % 2 == 1 {
+                hash_types.iter().map(|&hash_type| Hasher::new(8, hash_type)).chain(block_hashers()).collect()
+            } else {
+                block_hashers().collect()
+            };
+
+            for hasher in hashers {
+                let hashes = hasher.hash_dihedral(&img);
+
+                for &orientation in &Orientation::ALL {
+                    let turned = hasher.hash(&turn(&img, orientation));
+
+                    assert_eq!(*hashes.get(orientation), turned, "{}x{} {} {:?}",
+                               width, height, hasher.fingerprint(), orientation);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Measuring how well hashers tell copies of an image from other images, with the `eval` feature.
+//!
+//! An `Evaluation` derives variants of each image with standard `Transform`s, then hashes the
+//! images and their variants with each hasher. Each image against its own variants is a pair
+//! that should match and each image against the variants of the others a pair that should not;
+//! the `Report` for each hasher gives, for every distance threshold, how many of each match.
+//!
+//! Transforms that need randomness draw it from a generator seeded per image and transform, so
+//! evaluations are reproducible.
+use std::borrow::Borrow;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+use {Hasher, ImageHash};
+
+/// A change to an image that its copies commonly go through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+    /// Encode as JPEG at this quality, from 1 to 100, and decode again.
+    Jpeg(u8),
+    /// Resize by this factor.
+    Resize(f32),
+    /// Crop to the centre, keeping this fraction of the width and height.
+    Crop(f32),
+    /// Blur with a Gaussian kernel of this standard deviation, in pixels.
+    Blur(f32),
+    /// Add this value to every channel.
+    Brightness(i32),
+    /// Raise every channel, in `[0, 1]`, to this power.
+    Gamma(f32),
+    /// Add uniform noise of up to this value to every channel.
+    Noise(u8),
+    /// Rotate about the centre by this many degrees clockwise, keeping the size of the image and
+    /// repeating its edges into the corners.
+    Rotate(f32),
+    /// Overlay a striped white logo of this opacity, in `[0, 1]`, over the bottom right corner.
+    Watermark(f32),
+}
+
+impl Transform {
+    /// A moderate amount of each kind of transform.
+    pub fn standard() -> Vec<Transform> {
+        vec![
+            Transform::Jpeg(50),
+            Transform::Resize(0.5),
+            Transform::Crop(0.9),
+            Transform::Blur(1.5),
+            Transform::Brightness(30),
+            Transform::Gamma(1.5),
+            Transform::Noise(20),
+            Transform::Rotate(3.),
+            Transform::Watermark(0.5),
+        ]
+    }
+
+    /// A short string identifying this transform.
+    pub fn name(&self) -> String {
+        match *self {
+            Transform::Jpeg(quality) => format!("jpeg{}", quality),
+            Transform::Resize(factor) => format!("resize{}", factor),
+            Transform::Crop(keep) => format!("crop{}", keep),
+            Transform::Blur(sigma) => format!("blur{}", sigma),
+            Transform::Brightness(delta) => format!("brightness{}", delta),
+            Transform::Gamma(gamma) => format!("gamma{}", gamma),
+            Transform::Noise(amplitude) => format!("noise{}", amplitude),
+            Transform::Rotate(degrees) => format!("rotate{}", degrees),
+            Transform::Watermark(opacity) => format!("watermark{}", opacity),
+        }
+    }
+
+    /// Apply this transform to `img`, drawing any randomness from a generator seeded with `seed`.
+    pub fn apply(&self, img: &DynamicImage, seed: u64) -> DynamicImage {
+        let (width, height) = (img.width(), img.height());
+        let scaled = |len: u32, factor: f32| ((len as f32 * factor).round() as u32).max(1);
+
+        match *self {
+            Transform::Jpeg(quality) => {
+                let mut jpeg = Vec::new();
+
+                JpegEncoder::new_with_quality(&mut jpeg, quality.clamp(1, 100))
+                    .encode_image(&img.to_rgb8())
+                    .and_then(|_| image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg))
+                    .expect("re-encoding an RGB image in memory as JPEG")
+            },
+            Transform::Resize(factor) =>
+                img.resize_exact(scaled(width, factor), scaled(height, factor), FilterType::Triangle),
+            Transform::Crop(keep) => {
+                let keep = keep.clamp(0., 1.);
+                let (crop_width, crop_height) = (scaled(width, keep), scaled(height, keep));
+                img.crop_imm((width - crop_width) / 2, (height - crop_height) / 2, crop_width, crop_height)
+            },
+            Transform::Blur(sigma) => img.blur(sigma),
+            Transform::Brightness(delta) => img.brighten(delta),
+            Transform::Gamma(gamma) => map_channels(img, |val| (val as f32 / 255.).powf(gamma) * 255.),
+            Transform::Noise(amplitude) => {
+                let mut rng = XorShift::new(seed);
+                let amplitude = amplitude as f32;
+                map_channels(img, |val| val as f32 + (rng.next_unit() * 2. - 1.) * amplitude)
+            },
+            Transform::Rotate(degrees) => rotate(&img.to_rgba8(), degrees).into(),
+            Transform::Watermark(opacity) => watermark(&img.to_rgba8(), opacity.clamp(0., 1.)).into(),
+        }
+    }
+}
+
+/// Replace the color channels of `img` with `map_fn` of them, leaving alpha.
+fn map_channels<F: FnMut(u8) -> f32>(img: &DynamicImage, mut map_fn: F) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+
+    for px in rgba.pixels_mut() {
+        for channel in &mut px.0[.. 3] {
+            *channel = map_fn(*channel).round().clamp(0., 255.) as u8;
+        }
+    }
+
+    rgba.into()
+}
+
+fn rotate(img: &RgbaImage, degrees: f32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (center_x, center_y) = (width as f32 / 2., height as f32 / 2.);
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        // The source of each pixel, turned back the other way.
+        let (dx, dy) = (x as f32 + 0.5 - center_x, y as f32 + 0.5 - center_y);
+        let src_x = center_x + dx * cos + dy * sin;
+        let src_y = center_y - dx * sin + dy * cos;
+
+        let clamp = |val: f32, len: u32| (val.floor().max(0.) as u32).min(len - 1);
+        *img.get_pixel(clamp(src_x, width), clamp(src_y, height))
+    })
+}
+
+fn watermark(img: &RgbaImage, opacity: f32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+
+    // A quarter of the width and a tenth of the height, a little in from the corner.
+    let (mark_width, mark_height) = ((width / 4).max(1), (height / 10).max(1));
+    let (left, top) = (width - mark_width - width / 20, height - mark_height - height / 20);
+
+    let mut out = img.clone();
+
+    for y in top .. top + mark_height {
+        for x in left .. left + mark_width {
+            // Diagonal stripes, as text would only partly cover the area.
+            if ((x - left) + (y - top)) % 6 < 3 {
+                let Rgba([r, g, b, a]) = *img.get_pixel(x, y);
+                let blend = |val: u8| (val as f32 * (1. - opacity) + 255. * opacity).round() as u8;
+                out.put_pixel(x, y, Rgba([blend(r), blend(g), blend(b), a]));
+            }
+        }
+    }
+
+    out
+}
+
+/// A small, fast, seedable generator, so evaluations don't depend on a particular `rand`.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Zero is a fixed point; spread small seeds over the state.
+        XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    /// A value in `[0, 1)`.
+    fn next_unit(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Hashes images and their transformed variants with several hashers and reports how well each
+/// tells the variants of an image from those of other images.
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+    hashers: Vec<Hasher>,
+    transforms: Vec<Transform>,
+    seed: u64,
+}
+
+impl Default for Evaluation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Evaluation {
+    /// Create an evaluation with no hashers and `Transform::standard()`.
+    pub fn new() -> Self {
+        Evaluation {
+            hashers: Vec::new(),
+            transforms: Transform::standard(),
+            seed: 0,
+        }
+    }
+
+    /// Add `hasher` to the hashers compared.
+    pub fn hasher(mut self, hasher: Hasher) -> Self {
+        self.hashers.push(hasher);
+        self
+    }
+
+    /// Set the transforms applied to each image.
+    pub fn transforms(self, transforms: &[Transform]) -> Self {
+        Evaluation { transforms: transforms.to_vec(), .. self }
+    }
+
+    /// Set the seed for the randomness of the transforms. `0` by default.
+    pub fn seed(self, seed: u64) -> Self {
+        Evaluation { seed, .. self }
+    }
+
+    /// Transform each of `images` and report, for each hasher in the order added, how well it
+    /// matches the images to their variants.
+    ///
+    /// Only the hashes are kept: each image's variants are hashed with every hasher and dropped
+    /// before the next image is transformed, so `images` can load its images one at a time.
+    /// Needs at least two images for pairs that should not match.
+    pub fn run<I>(&self, images: I) -> Vec<Report>
+    where I: IntoIterator, I::Item: Borrow<DynamicImage> {
+        // For each hasher, the hash of each image and of each of its variants.
+        let mut originals: Vec<Vec<ImageHash>> = vec![Vec::new(); self.hashers.len()];
+        let mut hashes: Vec<Vec<Vec<ImageHash>>> = vec![Vec::new(); self.hashers.len()];
+
+        for (i, img) in images.into_iter().enumerate() {
+            let img = img.borrow();
+
+            for (h, hasher) in self.hashers.iter().enumerate() {
+                originals[h].push(hasher.hash(img));
+                hashes[h].push(Vec::with_capacity(self.transforms.len()));
+            }
+
+            for (t, transform) in self.transforms.iter().enumerate() {
+                let seed = self.seed ^ ((i as u64) << 32 | t as u64);
+                let variant = transform.apply(img, seed);
+
+                for (h, hasher) in self.hashers.iter().enumerate() {
+                    hashes[h][i].push(hasher.hash(&variant));
+                }
+            }
+        }
+
+        self.hashers.iter().zip(originals.iter().zip(&hashes)).map(|(hasher, (originals, hashes))| {
+            let mut matches = Vec::new();
+            let mut non_matches = Vec::new();
+
+            for (i, original) in originals.iter().enumerate() {
+                for (j, variants) in hashes.iter().enumerate() {
+                    let dists = variants.iter().map(|variant| original.dist(variant));
+
+                    if i == j {
+                        matches.extend(dists);
+                    } else {
+                        non_matches.extend(dists);
+                    }
+                }
+            }
+
+            let hash_bits = originals.first().map_or(0, |hash| hash.bitv.len());
+            Report::new(hasher.fingerprint(), hash_bits, &matches, &non_matches)
+        }).collect()
+    }
+}
+
+/// How well pairs match at one distance threshold, from `Report::points`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThresholdPoint {
+    /// Pairs with a distance of at most this many bits count as matching.
+    pub threshold: usize,
+    /// The fraction of pairs that should match that do: the recall.
+    pub true_positive_rate: f64,
+    /// The fraction of pairs that should not match that do.
+    pub false_positive_rate: f64,
+    /// The fraction of pairs matching that should; `1` if none do.
+    pub precision: f64,
+}
+
+impl ThresholdPoint {
+    /// The fraction of pairs that should match that do; the same as `true_positive_rate`.
+    pub fn recall(&self) -> f64 {
+        self.true_positive_rate
+    }
+
+    /// The harmonic mean of precision and recall.
+    pub fn f1(&self) -> f64 {
+        let sum = self.precision + self.recall();
+        if sum > 0. { 2. * self.precision * self.recall() / sum } else { 0. }
+    }
+}
+
+/// The results of one hasher in an `Evaluation`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// The `Hasher::fingerprint()` of the hasher.
+    pub fingerprint: String,
+    /// The length of its hashes.
+    pub hash_bits: usize,
+    /// The number of pairs that should match, each image against its own variants.
+    pub matches: usize,
+    /// The number of pairs that should not match, each image against the variants of the others.
+    pub non_matches: usize,
+    /// The ROC curve and precision-recall for each threshold from `0` to `hash_bits`.
+    pub points: Vec<ThresholdPoint>,
+    /// The area under the ROC curve: the chance that a random pair that should match is closer
+    /// than a random pair that shouldn't, counting ties as half. `1` is perfect; `0.5` is chance.
+    pub auc: f64,
+}
+
+impl Report {
+    fn new(fingerprint: String, hash_bits: usize, matches: &[usize], non_matches: &[usize]) -> Self {
+        let at_most = |dists: &[usize], threshold: usize| dists.iter().filter(|&&dist| dist <= threshold).count();
+        let rate = |count: usize, total: usize| if total > 0 { count as f64 / total as f64 } else { 0. };
+
+        let points: Vec<_> = (0 ..= hash_bits).map(|threshold| {
+            let (true_pos, false_pos) = (at_most(matches, threshold), at_most(non_matches, threshold));
+
+            ThresholdPoint {
+                threshold,
+                true_positive_rate: rate(true_pos, matches.len()),
+                false_positive_rate: rate(false_pos, non_matches.len()),
+                precision: if true_pos + false_pos > 0 { rate(true_pos, true_pos + false_pos) } else { 1. },
+            }
+        }).collect();
+
+        // Trapezoids from the origin through each threshold.
+        let mut auc = 0.;
+        let mut last = (0., 0.);
+
+        for point in &points {
+            let (fpr, tpr) = (point.false_positive_rate, point.true_positive_rate);
+            auc += (fpr - last.0) * (tpr + last.1) / 2.;
+            last = (fpr, tpr);
+        }
+
+        Report {
+            fingerprint,
+            hash_bits,
+            matches: matches.len(),
+            non_matches: non_matches.len(),
+            points,
+            auc,
+        }
+    }
+
+    /// The threshold with the highest F1 score, balancing precision and recall.
+    pub fn best_f1(&self) -> Option<&ThresholdPoint> {
+        self.points.iter().max_by(|l, r| l.f1().total_cmp(&r.f1()))
+    }
+
+    /// The highest threshold at which at most `max_rate` of the pairs that should not match do.
+    pub fn at_false_positive_rate(&self, max_rate: f64) -> Option<&ThresholdPoint> {
+        self.points.iter().rev().find(|point| point.false_positive_rate <= max_rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{imageops, DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+
+    use super::*;
+
+    use HashType;
+
+    #[test]
+    fn ranks_hashers() {
+        let images: Vec<DynamicImage> = (0 .. 4)
+            .map(|i| {
+                let (fx, fy) = (5. + 3. * i as f64, 11. - 2. * i as f64);
+                GrayImage::from_fn(96, 64, |x, y| {
+                    Luma([(128. + 60. * (x as f64 / fx).sin() + 60. * (y as f64 / fy + i as f64).cos()) as u8])
+                }).into()
+            })
+            .collect();
+
+        let reports = Evaluation::new()
+            .hasher(Hasher::new(8, HashType::Gradient))
+            .hasher(Hasher::new(2, HashType::Mean))
+            .run(&images);
+
+        let (gradient, tiny) = (&reports[0], &reports[1]);
+        assert_eq!(gradient.fingerprint, "gradient:8");
+        assert_eq!((gradient.matches, gradient.non_matches), (4 * 9, 4 * 3 * 9));
+        assert_eq!(gradient.points.len(), gradient.hash_bits + 1);
+
+        let last = gradient.points.last().unwrap();
+        assert_eq!((last.true_positive_rate, last.false_positive_rate), (1., 1.));
+
+        assert!(gradient.auc > 0.9, "{}", gradient.auc);
+        assert!(gradient.auc > tiny.auc, "{} <= {}", gradient.auc, tiny.auc);
+        assert!(gradient.best_f1().unwrap().f1() > 0.5);
+    }
+
+    /// A `width * height` image with a different colour in every pixel.
+    fn numbered(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 16) as u8, (y * 16) as u8, (x + y * width) as u8, 255]))
+    }
+
+    #[test]
+    fn transforms_keep_or_scale_the_size() {
+        for &(width, height) in &[(1, 1), (3, 3), (40, 30)] {
+            let img: DynamicImage = numbered(width, height).into();
+
+            for transform in Transform::standard() {
+                let out = transform.apply(&img, 7);
+                let (out_width, out_height) = (out.width(), out.height());
+
+                match transform {
+                    Transform::Resize(factor) | Transform::Crop(factor) => {
+                        let scaled = |len: u32| ((len as f32 * factor).round() as u32).max(1);
+                        assert_eq!((out_width, out_height), (scaled(width), scaled(height)), "{:?}", transform);
+                    },
+                    _ => assert_eq!((out_width, out_height), (width, height), "{:?}", transform),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crop_keeps_the_centre() {
+        let img = numbered(4, 4);
+        let cropped = Transform::Crop(0.5).apply(&img.clone().into(), 0).to_rgba8();
+        assert_eq!(cropped, imageops::crop_imm(&img, 1, 1, 2, 2).to_image());
+
+        // An odd margin leaves the extra pixel on the right and bottom.
+        let img = numbered(3, 3);
+        let cropped = Transform::Crop(0.5).apply(&img.clone().into(), 0).to_rgba8();
+        assert_eq!(cropped, imageops::crop_imm(&img, 0, 0, 2, 2).to_image());
+
+        let img = numbered(1, 1);
+        assert_eq!(Transform::Crop(0.1).apply(&img.clone().into(), 0).to_rgba8(), img);
+    }
+
+    #[test]
+    fn resize_scales() {
+        let flat: DynamicImage = RgbaImage::from_pixel(3, 3, Rgba([90, 120, 150, 255])).into();
+
+        let bigger = Transform::Resize(2.).apply(&flat, 0).to_rgba8();
+        assert_eq!(bigger.dimensions(), (6, 6));
+        assert!(bigger.pixels().all(|px| *px == Rgba([90, 120, 150, 255])));
+
+        let img = numbered(1, 1);
+        assert_eq!(Transform::Resize(0.5).apply(&img.clone().into(), 0).to_rgba8(), img);
+    }
+
+    #[test]
+    fn rotate_turns_about_the_centre() {
+        let img = numbered(3, 3);
+
+        assert_eq!(Transform::Rotate(0.).apply(&img.clone().into(), 0).to_rgba8(), img);
+        assert_eq!(Transform::Rotate(90.).apply(&img.clone().into(), 0).to_rgba8(), imageops::rotate90(&img));
+        assert_eq!(Transform::Rotate(180.).apply(&img.clone().into(), 0).to_rgba8(), imageops::rotate180(&img));
+
+        let img = numbered(1, 1);
+        assert_eq!(Transform::Rotate(33.).apply(&img.clone().into(), 0).to_rgba8(), img);
+    }
+
+    #[test]
+    fn watermark_covers_the_corner() {
+        let black: DynamicImage = RgbaImage::from_pixel(3, 3, Rgba([0, 0, 0, 255])).into();
+        let marked = Transform::Watermark(1.).apply(&black, 0).to_rgba8();
+
+        for (x, y, px) in marked.enumerate_pixels() {
+            let expected = if (x, y) == (2, 2) { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) };
+            assert_eq!(*px, expected, "({}, {})", x, y);
+        }
+
+        let half = Transform::Watermark(0.5).apply(&black, 0).to_rgba8();
+        assert_eq!(*half.get_pixel(2, 2), Rgba([128, 128, 128, 255]));
+        assert_eq!(Transform::Watermark(0.).apply(&black, 0), black);
+
+        let one: DynamicImage = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255])).into();
+        assert_eq!(Transform::Watermark(1.).apply(&one, 0).to_rgba8().get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn run_takes_images_one_at_a_time() {
+        let images: Vec<DynamicImage> = (0 .. 3).map(|i| numbered(24 + i, 16).into()).collect();
+        let evaluation = Evaluation::new().hasher(Hasher::new(4, HashType::Mean)).hasher(Hasher::new(4, HashType::DCT));
+
+        assert_eq!(evaluation.run(&images), evaluation.run(images.into_iter()));
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Gradient hashes comparing neighbouring values in any combination of directions, for every
+//! gradient `HashType`, and ways of comparing the halves of `HashType::DoubleGradient` hashes
+//! separately.
+use bit_vec::BitVec;
+
+use {gradient_hash_impl, Column, Columns};
+
+/// The directions `HashType::MultiGradient` compares neighbouring values in; at least one.
+///
+/// Horizontal alone is the same as `HashType::Gradient`, and horizontal and vertical together
+/// the same as `HashType::DoubleGradient`, which leave out the last value of some lines.
+/// Any other combination compares every neighbouring pair in its grid, one more value than
+/// `hash_size` along each axis a direction steps along, and adds the bits of each direction in
+/// the order of the fields: vertical alone and each diagonal give `hash_size * hash_size` bits,
+/// and horizontal or vertical next to another direction `(hash_size + 1) * hash_size`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GradientDirections {
+    horizontal: bool,
+    vertical: bool,
+    diagonal: bool,
+    anti_diagonal: bool,
+}
+
+impl GradientDirections {
+    /// Compare each value against its right neighbour (`horizontal`), the one below it
+    /// (`vertical`), the one below and to the right (`diagonal`) and the one below and to the
+    /// left (`anti_diagonal`).
+    ///
+    /// Returns `None` if no direction is set.
+    pub fn new(horizontal: bool, vertical: bool, diagonal: bool, anti_diagonal: bool) -> Option<Self> {
+        let directions = GradientDirections { horizontal, vertical, diagonal, anti_diagonal };
+
+        if directions.mask() != 0 { Some(directions) } else { None }
+    }
+
+    /// All four directions.
+    pub fn all() -> Self {
+        GradientDirections { horizontal: true, vertical: true, diagonal: true, anti_diagonal: true }
+    }
+
+    /// Whether each value is compared against its right neighbour.
+    pub fn horizontal(self) -> bool {
+        self.horizontal
+    }
+
+    /// Whether each value is compared against the one below it.
+    pub fn vertical(self) -> bool {
+        self.vertical
+    }
+
+    /// Whether each value is compared against the one below and to the right of it.
+    pub fn diagonal(self) -> bool {
+        self.diagonal
+    }
+
+    /// Whether each value is compared against the one below and to the left of it.
+    pub fn anti_diagonal(self) -> bool {
+        self.anti_diagonal
+    }
+
+    /// The number of directions set.
+    pub fn count(self) -> u32 {
+        self.mask().count_ones()
+    }
+
+    /// The directions as the low four bits of a byte, in the order of the fields.
+    pub(crate) fn mask(self) -> u8 {
+        self.horizontal as u8 | (self.vertical as u8) << 1 | (self.diagonal as u8) << 2
+            | (self.anti_diagonal as u8) << 3
+    }
+
+    /// The directions of the low four bits of `mask`, or `None` if they are all unset.
+    pub(crate) fn from_mask(mask: u8) -> Option<Self> {
+        GradientDirections::new(mask & 1 != 0, mask & 2 != 0, mask & 4 != 0, mask & 8 != 0)
+    }
+
+    /// A short string identifying these directions in `Hasher::fingerprint()`.
+    pub(crate) fn fingerprint(self) -> String {
+        ["h", "v", "d", "a"].iter().enumerate()
+            .filter(|&(i, _)| self.mask() & 1 << i != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// The width and height of the grid the image is resized to: one more value than
+    /// `hash_size` along each axis a direction steps along.
+    pub(crate) fn grid_size(self, hash_size: u32) -> (u32, u32) {
+        let steps_x = self.horizontal || self.diagonal || self.anti_diagonal;
+        let steps_y = self.vertical || self.diagonal || self.anti_diagonal;
+
+        (hash_size + steps_x as u32, hash_size + steps_y as u32)
+    }
+
+    /// Call `line_fn` with each line of a grid of `grid_size()` whose neighbouring values the
+    /// bits compare, in order, and the number of values in it to compare.
+    fn foreach_line<'a, T, F>(self, values: &'a [T], hash_size: u32, mut line_fn: F)
+    where F: FnMut(&Column<'a, T>, u32) {
+        let (width, height) = self.grid_size(hash_size);
+        let (w, h) = (width as usize, height as usize);
+
+        let row = |y: usize| Column { data: &values[y * w ..], rowstride: 1 };
+
+        // As `Gradient` and `DoubleGradient`, which leave out the last value of some lines.
+        match self.mask() {
+            0b0001 => return (0 .. h).for_each(|y| line_fn(&row(y), hash_size)),
+            0b0011 => {
+                (0 .. h).for_each(|y| line_fn(&row(y), width));
+                return Columns::from_slice(values, w).for_each(|column| line_fn(&column, hash_size));
+            },
+            _ => (),
+        }
+
+        if self.horizontal {
+            (0 .. h).for_each(|y| line_fn(&row(y), width));
+        }
+
+        if self.vertical {
+            Columns::from_slice(values, w).for_each(|column| line_fn(&column, height));
+        }
+
+        // Lines down and to the right, starting from the left and top edges.
+        if self.diagonal {
+            for (x, y) in (0 .. h).rev().map(|y| (0, y)).chain((1 .. w).map(|x| (x, 0))) {
+                let diagonal = Column { data: &values[y * w + x ..], rowstride: w + 1 };
+                line_fn(&diagonal, (w - x).min(h - y) as u32);
+            }
+        }
+
+        // Lines down and to the left, starting from the top and right edges.
+        if self.anti_diagonal {
+            for (x, y) in (0 .. w).map(|x| (x, 0)).chain((1 .. h).map(|y| (w - 1, y))) {
+                let anti_diagonal = Column { data: &values[y * w + x ..], rowstride: w - 1 };
+                line_fn(&anti_diagonal, (x + 1).min(h - y) as u32);
+            }
+        }
+    }
+}
+
+/// The hash of a grid of `directions.grid_size(hash_size)`: whether each value is less than its
+/// neighbour in each direction.
+pub(crate) fn gradient_bits<T: PartialOrd>(values: &[T], hash_size: u32, directions: GradientDirections)
+    -> BitVec {
+    let mut bitv = BitVec::new();
+    directions.foreach_line(values, hash_size, |line, len| gradient_hash_impl(line, len, &mut bitv));
+    bitv
+}
+
+/// The absolute differences `gradient_bits()` compares, bit by bit.
+pub(crate) fn gradient_diffs(values: &[f64], hash_size: u32, directions: GradientDirections) -> Vec<f64> {
+    let mut diffs = Vec::new();
+
+    directions.foreach_line(values, hash_size, |line, len| {
+        diffs.extend((1 .. len as usize).map(|i| (line[i] - line[i - 1]).abs()));
+    });
+
+    diffs
+}
+
+/// How `AxisDists::combine()` makes one distance of the distances between the row and column
+/// halves of two `HashType::DoubleGradient` hashes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AxisCombine {
+    /// The total, the same as `ImageHash::dist()`.
+    Sum,
+    /// The larger distance: both halves must match.
+    Max,
+    /// The smaller distance: one half matching is enough, so an image stretched or squeezed
+    /// along one axis still matches.
+    Min,
+}
+
+/// The distances between the row and column halves of two `HashType::DoubleGradient` hashes,
+/// from `Hasher::axis_dists()`.
+///
+/// The row half of each plane is `hash_size + 1` bits longer than its column half.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AxisDists {
+    /// The distance between the bits comparing horizontal neighbours.
+    pub rows: usize,
+    /// The distance between the bits comparing vertical neighbours.
+    pub columns: usize,
+}
+
+impl AxisDists {
+    /// Make one distance of the two with `policy`.
+    pub fn combine(self, policy: AxisCombine) -> usize {
+        match policy {
+            AxisCombine::Sum => self.rows + self.columns,
+            AxisCombine::Max => self.rows.max(self.columns),
+            AxisCombine::Min => self.rows.min(self.columns),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    use {Hasher, HashType, ImageHash};
+
+    #[test]
+    fn directions_see_their_edges() {
+        // Horizontal stripes: the values only change going down.
+        let stripes = GrayImage::from_fn(64, 64, |_, y| Luma([if (y / 4) % 2 == 0 { 40 } else { 220 }]));
+        let flipped = GrayImage::from_fn(64, 64, |_, y| Luma([if (y / 4) % 2 == 0 { 220 } else { 40 }]));
+
+        let dist = |hash_type| ImageHash::hash(&stripes, 8, hash_type).dist(&ImageHash::hash(&flipped, 8, hash_type));
+
+        assert_eq!(dist(HashType::Gradient), 0);
+        assert!(dist(HashType::VerticalGradient) > 0);
+        assert!(dist(HashType::DiagonalGradient) > 0);
+
+        let directions = GradientDirections::new(true, false, false, true).unwrap();
+        let hasher = Hasher::new(8, HashType::MultiGradient(directions));
+        assert_eq!(hasher.hash(&stripes).bitv.len(), 9 * 8 + 64);
+        assert_eq!(hasher.fingerprint(), "multi-gradient:8:ha");
+        assert_eq!(hasher.hash(&stripes).dist(&hasher.hash(&flipped)), dist(HashType::MultiGradient(directions)));
+    }
+
+    #[test]
+    fn bit_lengths() {
+        let img = GrayImage::from_fn(50, 37, |x, y| Luma([((x * 7) ^ (y * 13)) as u8]));
+        let multi = |h, v, d, a| HashType::MultiGradient(GradientDirections::new(h, v, d, a).unwrap());
+
+        for &size in &[1, 2, 8, 13] {
+            let bits = |hash_type| ImageHash::hash(&img, size, hash_type).bitv.len() as u32;
+            let (square, lines) = (size * size, (size + 1) * size);
+
+            assert_eq!(bits(HashType::VerticalGradient), square);
+            assert_eq!(bits(HashType::DiagonalGradient), square);
+            assert_eq!(bits(multi(false, false, false, true)), square);
+            assert_eq!(bits(multi(false, false, true, true)), 2 * square);
+            assert_eq!(bits(multi(true, false, false, true)), lines + square);
+            assert_eq!(bits(multi(false, true, true, false)), lines + square);
+            assert_eq!(bits(multi(true, true, true, false)), 2 * lines + square);
+            assert_eq!(bits(HashType::MultiGradient(GradientDirections::all())), 2 * lines + 2 * square);
+        }
+    }
+
+    #[test]
+    fn rejects_no_directions() {
+        assert_eq!(GradientDirections::new(false, false, false, false), None);
+        assert_eq!(GradientDirections::from_mask(0x10), None);
+        assert_eq!(HashType::try_from_byte(0x10), None);
+        assert_eq!(HashType::try_from_byte(0x13),
+                   GradientDirections::new(true, true, false, false).map(HashType::MultiGradient));
+    }
+
+    #[test]
+    fn single_directions_match_fixed_gradients() {
+        let horizontal = HashType::MultiGradient(GradientDirections::new(true, false, false, false).unwrap());
+        let both = HashType::MultiGradient(GradientDirections::new(true, true, false, false).unwrap());
+        let vertical = HashType::MultiGradient(GradientDirections::new(false, true, false, false).unwrap());
+        let diagonal = HashType::MultiGradient(GradientDirections::new(false, false, true, false).unwrap());
+
+        let gray = GrayImage::from_fn(50, 37, |x, y| Luma([((x * 7) ^ (y * 13)) as u8]));
+        let color = ::image::RgbaImage::from_fn(64, 48, |x, y| {
+            ::image::Rgba([(x * y) as u8, (x * 5) as u8, (y * 3) as u8, 255])
+        });
+
+        for &(multi, fixed) in &[(horizontal, HashType::Gradient), (both, HashType::DoubleGradient),
+                                 (vertical, HashType::VerticalGradient), (diagonal, HashType::DiagonalGradient)] {
+            for &hash_size in &[4, 8, 16] {
+                assert_eq!(ImageHash::hash(&gray, hash_size, multi).bitv,
+                           ImageHash::hash(&gray, hash_size, fixed).bitv, "{:?} {}", fixed, hash_size);
+                assert_eq!(ImageHash::hash(&color, hash_size, multi).bitv,
+                           ImageHash::hash(&color, hash_size, fixed).bitv, "{:?} {}", fixed, hash_size);
+
+                let (multi, fixed) = (Hasher::new(hash_size, multi).per_channel(::Channels::Rgb),
+                                      Hasher::new(hash_size, fixed).per_channel(::Channels::Rgb));
+                assert_eq!(multi.hash(&color).bitv, fixed.hash(&color).bitv, "{:?} {}", fixed, hash_size);
+                assert_eq!(multi.hash_with_quality(&color).0.bitv, fixed.hash_with_quality(&color).0.bitv);
+            }
+        }
+    }
+
+    #[test]
+    fn double_gradient_halves() {
+        let stripes = GrayImage::from_fn(64, 64, |_, y| Luma([if (y / 4) % 2 == 0 { 40 } else { 220 }]));
+        let flipped = GrayImage::from_fn(64, 64, |_, y| Luma([if (y / 4) % 2 == 0 { 220 } else { 40 }]));
+
+        let hasher = Hasher::new(8, HashType::DoubleGradient);
+        let (hash, other) = (hasher.hash(&stripes), hasher.hash(&flipped));
+
+        let (rows, columns) = hasher.axis_halves(&hash).unwrap();
+        assert_eq!((rows.len(), columns.len()), (72, 63));
+        assert_eq!(rows.iter().chain(columns.iter()).collect::<BitVec>(), hash.bitv);
+
+        // Only the columns see the stripes.
+        let dists = hasher.axis_dists(&hash, &other).unwrap();
+        assert_eq!(dists.rows, 0);
+        assert!(dists.columns > 0);
+
+        assert_eq!(dists.combine(AxisCombine::Sum), hash.dist(&other));
+        assert_eq!(dists.combine(AxisCombine::Max), dists.columns);
+        assert_eq!(dists.combine(AxisCombine::Min), 0);
+
+        // Each plane has its own halves.
+        let color = hasher.clone().per_channel(::Channels::Rgb);
+        let color_dists = color.axis_dists(&color.hash(&stripes), &color.hash(&flipped)).unwrap();
+        assert_eq!(color_dists, AxisDists { rows: 0, columns: 3 * dists.columns });
+
+        assert!(hasher.axis_halves(&ImageHash::hash(&stripes, 8, HashType::Gradient)).is_none());
+    }
+}
@@ -6,7 +6,8 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 //! A crate that provides several perceptual hashing algorithms for images.
-//! Supports images opened with the [image][1] crate from Piston.
+//! Supports images opened with the [image][1] crate from Piston, version 0.25
+//! (or 0.10 through 0.19 with the `rust-image-legacy` feature).
 //!
 //!
 //! ### Example
@@ -44,8 +45,14 @@ extern crate bit_vec;
 #[cfg(any(test, feature = "rust-image"))]
 extern crate image;
 
+#[cfg(feature = "rust-image-legacy")]
+extern crate image_legacy;
+
 extern crate rustc_serialize as serialize;
 
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
 use serialize::base64::{ToBase64, FromBase64, STANDARD};
 // Needs to be fully qualified
 pub use serialize::base64::FromBase64Error;
@@ -55,15 +62,73 @@ use bit_vec::BitVec;
 use dct::dct_2d;
 
 use std::{fmt, hash, ops};
+use std::sync::Arc;
 
 #[cfg(any(test, feature = "rust-image"))]
 mod rust_image;
 
+#[cfg(feature = "rust-image")]
+pub use rust_image::DynamicGrayImage;
+
+#[cfg(feature = "rust-image-legacy")]
+mod rust_image_legacy;
+
 mod dct;
 
 mod block;
 
-pub use dct::precompute_dct_matrix;
+mod raw;
+
+mod dihedral;
+
+mod trim;
+
+mod color;
+
+mod quality;
+
+mod quantize;
+
+mod gradient;
+
+mod cells;
+
+#[cfg(feature = "rayon")]
+pub mod par;
+
+pub mod cache;
+
+pub mod video;
+
+pub mod segment;
+
+pub mod tiles;
+
+pub mod preprocess;
+
+#[cfg(feature = "rust-image")]
+pub mod animation;
+
+#[cfg(feature = "eval")]
+pub mod eval;
+
+pub use dct::{precompute_dct_matrix, DctCache};
+
+pub use dihedral::{DihedralHashes, Orientation};
+
+pub use trim::{CropRect, TrimBorders};
+
+pub use color::{AlphaPolicy, Channels, ColorChannel, GrayConversion};
+
+pub use block::BlockhashMethod;
+
+pub use gradient::{AxisCombine, AxisDists, GradientDirections};
+
+pub use quality::{HashQuality, SoftHash};
+
+use preprocess::{GrayBuffer, Preprocessor};
+
+pub use raw::{LumaWeights, PixelFormat, RawImage, RawImageError, YuvFormat, YuvImage};
 
 /// A struct representing an image processed by a perceptual hash.
 /// For efficiency, does not retain a copy of the image data after hashing.
@@ -79,10 +144,14 @@ pub struct ImageHash {
 
 impl ImageHash {
     /// Create a hash of `img` with a length of `hash_size * hash_size`
-    /// (`* 2` when using `HashType::DoubleGradient`)
+    /// (`* 2` when using `HashType::DoubleGradient`; see `GradientDirections` for
+    /// `HashType::MultiGradient`)
     /// using the hash algorithm described by `hash_type`.
+    ///
+    /// `HashType::DCT` uses the matrices precomputed with `precompute_dct_matrix()`, if any.
+    #[allow(clippy::redundant_field_names)]
     pub fn hash<I: HashImage>(img: &I, hash_size: u32, hash_type: HashType) -> ImageHash {
-        let hash = hash_type.hash(img, hash_size);
+        let hash = hash_type.hash(img, hash_size, DctCache::global());
 
         ImageHash {
             bitv: hash,
@@ -107,6 +176,48 @@ impl ImageHash {
             .filter(|&(left, right)| left != right).count()
     }
 
+    /// Calculate the distance between `self` and `other`, hashed with `Hasher::quantize()` set
+    /// to `bits_per_cell`: the sum of how many levels apart each of their cells is.
+    ///
+    /// For one bit per cell this is the Hamming distance.
+    ///
+    /// ###Panics
+    /// If `self` and `other` have differing `bitv` lengths or `hash_type` values.
+    pub fn quantized_dist(&self, other: &ImageHash, bits_per_cell: u8) -> usize {
+        assert_eq!(self.hash_type, other.hash_type,
+               "Image hashes must use the same algorithm for proper comparison!");
+        assert_eq!(self.bitv.len(), other.bitv.len(),
+                   "Image hashes must be the same length for proper comparison!");
+
+        quantize::level_dist(&self.bitv, &other.bitv, bits_per_cell.max(1))
+    }
+
+    /// Calculate the Hamming distance between `self` and `other`, counting only the bits set in
+    /// `mask`, such as one from `SoftHash::reliable_bits()`.
+    pub fn dist_masked(&self, other: &ImageHash, mask: &BitVec) -> usize {
+        assert_eq!(self.bitv.len(), mask.len(), "The mask must be as long as the hashes!");
+
+        self.differing_bits(other).iter().zip(mask.iter())
+            .filter(|&(differs, keep)| differs && keep).count()
+    }
+
+    /// The bits that differ between `self` and `other`.
+    fn differing_bits(&self, other: &ImageHash) -> BitVec {
+        assert_eq!(self.hash_type, other.hash_type,
+               "Image hashes must use the same algorithm for proper comparison!");
+        assert_eq!(self.bitv.len(), other.bitv.len(),
+                   "Image hashes must be the same length for proper comparison!");
+
+        let mut differing = self.bitv.clone();
+        differing.difference(&other.bitv);
+
+        let mut reverse = other.bitv.clone();
+        reverse.difference(&self.bitv);
+
+        differing.union(&reverse);
+        differing
+    }
+
     /// Calculate the Hamming distance between `self` and `other`,
     /// then normalize it to `[0, 1]`, as a fraction of the total bits.
     /// 
@@ -139,6 +250,8 @@ impl ImageHash {
     /// Does **not** preserve the internal value of `HashType::UserDCT`.
     /// ## Errors:
     /// Returns a FromBase64Error::InvalidBase64Length when trying to hash a zero-length string
+    #[allow(deprecated)] // `try!`
+    #[allow(clippy::explicit_auto_deref, clippy::len_zero, clippy::redundant_field_names)]
     pub fn from_base64(encoded_hash: &str) -> Result<ImageHash, FromBase64Error>{
         let mut data = try!(encoded_hash.from_base64());
         // The hash type should be the first bit of the hash
@@ -165,6 +278,350 @@ impl ImageHash {
     }
 }
 
+/// A reusable hash configuration: a hash size and algorithm, plus any state
+/// the algorithm can keep between images.
+///
+/// `Hasher::new(size, hash_type).hash(&img)` is equivalent to `ImageHash::hash(&img, size,
+/// hash_type)`.
+///
+/// Give each `Hasher` its own `DctCache` with `Hasher::dct_cache()` (or share one between them)
+/// to have `HashType::DCT` compute its matrix on first use and keep it, regardless of what
+/// other hash sizes are in use in the process.
+#[derive(Clone, Debug)]
+pub struct Hasher {
+    hash_size: u32,
+    hash_type: HashType,
+    dct_cache: Option<DctCache>,
+    exif_orientation: bool,
+    trim: Option<TrimBorders>,
+    grayscale: GrayConversion,
+    channels: Option<Channels>,
+    alpha: Option<AlphaPolicy>,
+    blockhash: Option<BlockhashMethod>,
+    preprocess: Vec<Arc<dyn Preprocessor>>,
+    bits_per_cell: u8,
+}
+
+impl Hasher {
+    /// Create a hasher producing hashes of `hash_size * hash_size` bits with `hash_type`.
+    pub fn new(hash_size: u32, hash_type: HashType) -> Self {
+        Hasher {
+            hash_size,
+            hash_type,
+            dct_cache: None,
+            exif_orientation: false,
+            trim: None,
+            grayscale: GrayConversion::Native,
+            channels: None,
+            alpha: None,
+            blockhash: None,
+            preprocess: Vec::new(),
+            bits_per_cell: 1,
+        }
+    }
+
+    /// Use `cache` for the DCT matrices of this hasher instead of `DctCache::global()`.
+    ///
+    /// The hasher adds every matrix it needs to `cache`, so they are only computed once.
+    pub fn dct_cache(self, cache: DctCache) -> Self {
+        Hasher { dct_cache: Some(cache), .. self }
+    }
+
+    /// Rotate and mirror images decoded by `hash_path()` and `hash_bytes()` as their EXIF
+    /// Orientation tag says before hashing them, so photos hash the same whether or not the
+    /// rotation was already applied to their pixels. Off by default.
+    ///
+    /// Images passed to `hash()` are already decoded and hashed as they are.
+    #[cfg(feature = "rust-image")]
+    pub fn exif_orientation(self, apply: bool) -> Self {
+        Hasher { exif_orientation: apply, .. self }
+    }
+
+    /// Crop near-uniform borders, such as black bars, off images before hashing them.
+    ///
+    /// Only applies to image types that implement `HashImage::crop()`.
+    pub fn trim_borders(self, trim: TrimBorders) -> Self {
+        Hasher { trim: Some(trim), .. self }
+    }
+
+    /// Convert color images to grayscale with `conversion` instead of `HashImage::grayscale()`.
+    pub fn grayscale(self, conversion: GrayConversion) -> Self {
+        Hasher { grayscale: conversion, .. self }
+    }
+
+    /// Hash each of the three planes of `channels` separately and concatenate the results into
+    /// one hash three times as long, so differences in color count as well as in brightness.
+    /// Overrides `grayscale()`.
+    pub fn per_channel(self, channels: Channels) -> Self {
+        Hasher { channels: Some(channels), .. self }
+    }
+
+    /// Handle the alpha channel of images with `policy`, the same way for every algorithm.
+    ///
+    /// Unset by default, which keeps the behavior of earlier versions: `HashType::Block` counts
+    /// fully transparent pixels as white and the other algorithms ignore alpha.
+    pub fn alpha(self, policy: AlphaPolicy) -> Self {
+        Hasher { alpha: Some(policy), .. self }
+    }
+
+    /// Hash `HashType::Block` with the block sums and median comparisons of the reference
+    /// blockhash.io implementations' `method`.
+    ///
+    /// Without it, `HashType::Block` keeps the hashes of earlier versions, whose block sums do
+    /// not line up with the blocks: images wider than they are tall, or whose size isn't a
+    /// multiple of the number of blocks, are split into blocks differently. It also compares
+    /// the blocks to the medians slightly differently and keeps the precision of 16-bit and
+    /// floating-point images.
+    pub fn blockhash_method(self, method: BlockhashMethod) -> Self {
+        Hasher { blockhash: Some(method), .. self }
+    }
+
+    /// Add `step` to the end of the preprocessing chain run on the grayscale image before it
+    /// is resized and hashed; see the `preprocess` module. Empty by default.
+    pub fn preprocess<P: Preprocessor + 'static>(mut self, step: P) -> Self {
+        self.preprocess.push(Arc::new(step));
+        self
+    }
+
+    /// Emit `bits_per_cell` bits, from 1 to 8, for each cell of the hash instead of one: which
+    /// side of its threshold the value of the cell is on and, in even bins, how far from it.
+    /// Compare the hashes with `ImageHash::quantized_dist()`.
+    ///
+    /// Hashes are `bits_per_cell` times as long; the first bit of each cell is the bit it would
+    /// otherwise have. `hash_dihedral()` ignores this and always emits one bit per cell.
+    ///
+    /// `bits_per_cell` is clamped to `[1, 8]`.
+    pub fn quantize(self, bits_per_cell: u8) -> Self {
+        Hasher { bits_per_cell: bits_per_cell.clamp(1, 8), .. self }
+    }
+
+    /// Get the hash size this hasher was created with.
+    pub fn hash_size(&self) -> u32 { self.hash_size }
+
+    /// Get the `HashType` this hasher was created with.
+    pub fn hash_type(&self) -> HashType { self.hash_type }
+
+    /// A string identifying every part of this hasher's configuration that affects its output.
+    ///
+    /// Hashes are only comparable if they came from hashers with the same fingerprint,
+    /// which makes this suitable for keying persistent caches like `cache::HashCache`.
+    ///
+    /// ## Note
+    /// For `HashType::UserDCT` the fingerprint does not identify the user's DCT function.
+    pub fn fingerprint(&self) -> String {
+        let mut fingerprint = format!("{}:{}", self.hash_type.name(), self.hash_size);
+
+        if let (HashType::Block, Some(method)) = (self.hash_type, self.blockhash) {
+            fingerprint.push_str(":blockhash-");
+            fingerprint.push_str(method.fingerprint());
+        }
+
+        if let HashType::MultiGradient(directions) = self.hash_type {
+            fingerprint.push(':');
+            fingerprint.push_str(&directions.fingerprint());
+        }
+
+        if self.exif_orientation {
+            fingerprint.push_str(":exif");
+        }
+
+        if let Some(ref trim) = self.trim {
+            fingerprint.push(':');
+            fingerprint.push_str(&trim.fingerprint());
+        }
+
+        if let Some(channels) = self.channels {
+            fingerprint.push_str(":channels-");
+            fingerprint.push_str(channels.fingerprint());
+        } else if self.grayscale != GrayConversion::Native {
+            fingerprint.push_str(":gray-");
+            fingerprint.push_str(&self.grayscale.fingerprint());
+        }
+
+        if let Some(alpha) = self.alpha {
+            fingerprint.push_str(":alpha-");
+            fingerprint.push_str(&alpha.fingerprint());
+        }
+
+        if !self.preprocess.is_empty() {
+            let steps: Vec<_> = self.preprocess.iter().map(|step| step.fingerprint()).collect();
+            fingerprint.push(':');
+            fingerprint.push_str(&steps.join("+"));
+        }
+
+        if self.bits_per_cell > 1 {
+            fingerprint.push_str(&format!(":quantize{}", self.bits_per_cell));
+        }
+
+        fingerprint
+    }
+
+    /// Hash `img` with this hasher's configuration.
+    pub fn hash<I: HashImage>(&self, img: &I) -> ImageHash {
+        self.hash_with_crop(img).0
+    }
+
+    /// Hash `img` with this hasher's configuration, also returning the part of the image that
+    /// was hashed after trimming borders (all of it, if `trim_borders()` is not set).
+    pub fn hash_with_crop<I: HashImage>(&self, img: &I) -> (ImageHash, CropRect) {
+        let (cells, rect) = self.cells(img);
+        let bitv = if self.bits_per_cell > 1 {
+            cells.iter().flat_map(|cells| self.quantized(&cells.bits(), &cells.margins())).collect()
+        } else {
+            cells.iter().flat_map(|cells| cells.bits()).collect()
+        };
+
+        (ImageHash { bitv, hash_type: self.hash_type }, rect)
+    }
+
+    /// Hash `img` with this hasher's configuration, also measuring how much information the
+    /// hash carries; see `HashQuality`.
+    pub fn hash_with_quality<I: HashImage>(&self, img: &I) -> (ImageHash, HashQuality) {
+        let (cells, _) = self.cells(img);
+        let mut bitv = BitVec::new();
+
+        let qualities: Vec<_> = cells.iter().map(|cells| {
+            let (bits, margins) = (cells.bits(), cells.margins());
+            bitv.extend(self.quantized(&bits, &margins));
+            cells.quality(&bits, &margins)
+        }).collect();
+
+        let quality = HashQuality::merge(&qualities, &bitv);
+
+        (ImageHash { bitv, hash_type: self.hash_type }, quality)
+    }
+
+    /// Hash `img` with this hasher's configuration, keeping how close each bit was to flipping;
+    /// see `SoftHash`.
+    ///
+    /// With `quantize()` set, every bit of a cell has the margin of the cell.
+    pub fn hash_soft<I: HashImage>(&self, img: &I) -> SoftHash {
+        let (cells, _) = self.cells(img);
+        let bits_per_cell = self.bits_per_cell as usize;
+        let (mut bitv, mut soft_margins) = (BitVec::new(), Vec::new());
+
+        for cells in &cells {
+            let (bits, margins) = (cells.bits(), cells.margins());
+            bitv.extend(self.quantized(&bits, &margins));
+            soft_margins.extend(margins.iter()
+                .flat_map(|&margin| ::std::iter::repeat(margin as f32).take(bits_per_cell)));
+        }
+
+        SoftHash { hash: ImageHash { bitv, hash_type: self.hash_type }, margins: soft_margins }
+    }
+
+    /// Split a `HashType::DoubleGradient` hash from this hasher into the bits comparing
+    /// horizontal neighbours and the bits comparing vertical ones, in that order, joining the
+    /// halves of each plane with `per_channel()` set.
+    ///
+    /// Returns `None` if this hasher or `hash` uses another algorithm, or `hash` has a length
+    /// this hasher can't produce.
+    pub fn axis_halves(&self, hash: &ImageHash) -> Option<(BitVec, BitVec)> {
+        if self.hash_type != HashType::DoubleGradient || hash.hash_type != HashType::DoubleGradient {
+            return None;
+        }
+
+        let (size, bits_per_cell) = (self.hash_size as usize, self.bits_per_cell as usize);
+        let rows_len = (size + 1) * size * bits_per_cell;
+        let plane_len = rows_len + (size + 1) * size.saturating_sub(1) * bits_per_cell;
+
+        if plane_len == 0 || hash.bitv.len() % plane_len != 0 {
+            return None;
+        }
+
+        let (mut rows, mut columns) = (BitVec::new(), BitVec::new());
+
+        for (i, bit) in hash.bitv.iter().enumerate() {
+            if i % plane_len < rows_len {
+                rows.push(bit);
+            } else {
+                columns.push(bit);
+            }
+        }
+
+        Some((rows, columns))
+    }
+
+    /// Calculate the Hamming distances between the halves of two `HashType::DoubleGradient`
+    /// hashes from this hasher separately; see `axis_halves()`.
+    ///
+    /// Stretching or squeezing an image along one axis mostly changes only one half, so
+    /// matching with `AxisCombine::Min` tolerates it.
+    ///
+    /// ###Panics
+    /// If `left` and `right` have differing `bitv` lengths or `hash_type` values.
+    pub fn axis_dists(&self, left: &ImageHash, right: &ImageHash) -> Option<AxisDists> {
+        let differing = ImageHash { bitv: left.differing_bits(right), hash_type: left.hash_type };
+        let (rows, columns) = self.axis_halves(&differing)?;
+        let count = |bits: BitVec| bits.iter().filter(|&differs| differs).count();
+
+        Some(AxisDists { rows: count(rows), columns: count(columns) })
+    }
+
+    /// Hash `img` in all eight orientations it could have been rotated or mirrored to.
+    ///
+    /// Costs little more than `hash()`: the image is only decoded and resized once (twice for
+    /// `HashType::Gradient` and the other gradients on non-square grids) and each orientation
+    /// is derived from the same values.
+    ///
+    /// `HashType::Block` hashes match those of the actually rotated or mirrored image. The other
+    /// algorithms resize the image, and the nearest-neighbour filter `image` buffers are resized
+    /// with can fall exactly between two pixels along an axis an even number of pixels long,
+    /// picking the other pixel once the image is turned; a few bits may then differ. Images whose
+    /// width and height are both odd always match.
+    pub fn hash_dihedral<I: HashImage>(&self, img: &I) -> DihedralHashes {
+        trim::with_trimmed(self.trim.as_ref(), img, |img| {
+            match self.planes(img) {
+                Some(planes) => DihedralHashes::concat(planes.iter().map(|plane| self.dihedral(plane))),
+                None => self.dihedral(img),
+            }
+        }).0
+    }
+
+    /// What the bits of each plane of `img` are decided from, and the part of it that was
+    /// hashed. Every other way of hashing with a `Hasher` goes through here, so they all agree.
+    fn cells<I: HashImage>(&self, img: &I) -> (Vec<cells::Cells>, CropRect) {
+        trim::with_trimmed(self.trim.as_ref(), img, |img| match self.planes(img) {
+            Some(planes) => planes.iter().map(|plane| self.plane_cells(plane)).collect(),
+            None => vec![self.plane_cells(img)],
+        })
+    }
+
+    fn plane_cells<I: HashImage>(&self, img: &I) -> cells::Cells {
+        cells::Cells::new(self.hash_type, img, self.hash_size, self.dct_cache_or_global(), self.blockhash)
+    }
+
+    /// `bits` with `quantize()` applied, each cell binned by its margin.
+    fn quantized(&self, bits: &BitVec, margins: &[f64]) -> BitVec {
+        if self.bits_per_cell == 1 {
+            return bits.clone();
+        }
+
+        let margins: Vec<f32> = margins.iter().map(|&margin| margin as f32).collect();
+        quantize::quantize(bits, &margins, self.bits_per_cell)
+    }
+
+    fn dihedral<I: HashImage>(&self, img: &I) -> DihedralHashes {
+        dihedral::hash_dihedral(self.hash_type, img, self.hash_size, self.dct_cache_or_global(), self.blockhash)
+    }
+
+    /// The preprocessed grayscale planes of `img` to hash, or `None` to hash it as it is.
+    fn planes<I: HashImage>(&self, img: &I) -> Option<Vec<GrayBuffer>> {
+        let planes = match self.channels {
+            Some(channels) => color::channel_planes(img, channels, self.alpha),
+            None if self.grayscale != GrayConversion::Native || self.alpha.is_some() || !self.preprocess.is_empty() =>
+                vec![color::gray_plane(img, self.grayscale, self.alpha)],
+            None => return None,
+        };
+
+        Some(planes.into_iter().map(|plane| plane.preprocessed(&self.preprocess)).collect())
+    }
+
+    fn dct_cache_or_global(&self) -> &DctCache {
+        self.dct_cache.as_ref().unwrap_or_else(|| DctCache::global())
+    }
+}
+
 /// The length of a row in a 2D matrix when packed into a 1D array.
 pub type Rowstride = usize;
 
@@ -228,6 +685,7 @@ impl hash::Hash for DCT2DFunc {
 
 /// An enum describing the hash algorithms that `img_hash` offers.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum HashType { 
     /// This algorithm first averages the pixels of the reduced-size and color image,
     /// and then compares each pixel to the average.
@@ -238,6 +696,9 @@ pub enum HashType {
     ///
     /// Faster than `Mean` but also prone to more collisions and suitable only for finding
     /// duplicates.
+    ///
+    /// Kept as in earlier versions, so stored hashes still match; set
+    /// `Hasher::blockhash_method()` to follow the reference implementations instead.
     Block,
     /// This algorithm compares each pixel in a row to its neighbor and registers changes in
     /// gradients (e.g. edges and color boundaries).
@@ -249,13 +710,30 @@ pub enum HashType {
     /// 
     /// Slower than `Gradient` and produces a double-sized hash, but much more accurate.
     DoubleGradient,
+    /// A version of `Gradient` that compares each pixel to the one below it instead, picking up
+    /// horizontal edges.
+    ///
+    /// Unlike `Gradient`, compares every pair in its `hash_size + 1` values per column, for
+    /// exactly `hash_size * hash_size` bits.
+    VerticalGradient,
+    /// A version of `Gradient` that compares each pixel to the one below and to the right of it,
+    /// picking up edges in both directions with a single-sized hash of `hash_size * hash_size`
+    /// bits.
+    DiagonalGradient,
+    /// `Gradient` in any combination of directions, each adding about `hash_size * hash_size`
+    /// bits, to trade the length of the hash against how many kinds of edges it picks up.
+    ///
+    /// With only the horizontal direction this is `Gradient`, with the horizontal and vertical
+    /// ones `DoubleGradient`; `VerticalGradient` and `DiagonalGradient` are this with a single
+    /// direction. See `GradientDirections` for the layout of the bits.
+    MultiGradient(GradientDirections),
     /// This algorithm runs a Discrete Cosine Transform on the reduced-color and size image,
     /// then compares each datapoint in the transform to the average.
     ///
     /// Slowest by far, but can detect changes in color gamut and sometimes relatively significant edits.
     ///
-    /// Call `precompute_dct_matrix()` with your chosen hash size to memoize the DCT matrix for the
-    /// given size, which can produce significant speedups in repeated hash runs.
+    /// The DCT matrix for each hash size is computed once and kept in a `DctCache`;
+    /// call `precompute_dct_matrix()` with your chosen hash size to compute it ahead of time.
     DCT,
     /// Equivalent to `DCT`, but allows the user to provide their own 2-dimensional DCT function. 
     /// See the `DCT2DFunc` docs for more info.
@@ -271,18 +749,8 @@ pub enum HashType {
 }
 
 impl HashType {
-    fn hash<I: HashImage>(self, img: &I, hash_size: u32) -> BitVec {
-        use HashType::*; 
-
-        match self {
-            Mean => mean_hash(img, hash_size),
-            Block => block::blockhash(img, hash_size),
-            DCT => dct_hash(img, hash_size, DCT2DFunc(dct_2d)),
-            Gradient => gradient_hash(img, hash_size),
-            DoubleGradient => double_gradient_hash(img, hash_size),
-            UserDCT(dct_2d_func) => dct_hash(img, hash_size, dct_2d_func),
-            __BackCompat => panic!("`HashType::__BackCompat` is not an actual hash algorithm"),
-        }
+    fn hash<I: HashImage>(self, img: &I, hash_size: u32, dct_cache: &DctCache) -> BitVec {
+        cells::Cells::new(self, img, hash_size, dct_cache, None).bits()
     }
 
     fn to_byte(self) -> u8 {
@@ -295,55 +763,162 @@ impl HashType {
             Gradient => 3,
             DoubleGradient => 4,
             UserDCT(_) => 5,
+            VerticalGradient => 7,
+            DiagonalGradient => 8,
+            // Keeps the directions.
+            MultiGradient(directions) => 0x10 | directions.mask(),
             __BackCompat => panic!("`HashType::__BackCompat` is not an actual hash algorithm"),
         }
     }
 
     fn from_byte(byte: u8) -> HashType {
+        Self::try_from_byte(byte)
+            .unwrap_or_else(|| panic!("Byte {:?} cannot be coerced to a `HashType`!", byte))
+    }
+
+    fn try_from_byte(byte: u8) -> Option<HashType> {
         use HashType::*;
 
-        match byte {
+        Some(match byte {
             1 => Mean,
             2 => DCT,
             3 => Gradient,
             4 => DoubleGradient,
             5 => UserDCT(DCT2DFunc(dct_2d)),
             6 => Block,
-            _ => panic!("Byte {:?} cannot be coerced to a `HashType`!", byte),
+            7 => VerticalGradient,
+            8 => DiagonalGradient,
+            0x11 ..= 0x1f => MultiGradient(GradientDirections::from_mask(byte)?),
+            _ => return None,
+        })
+    }
+
+    /// A short, stable name for use in `Hasher::fingerprint()`.
+    fn name(self) -> &'static str {
+        use HashType::*;
+
+        match self {
+            Mean => "mean",
+            Block => "block",
+            DCT => "dct",
+            Gradient => "gradient",
+            DoubleGradient => "double-gradient",
+            VerticalGradient => "vertical-gradient",
+            DiagonalGradient => "diagonal-gradient",
+            MultiGradient(_) => "multi-gradient",
+            UserDCT(_) => "user-dct",
+            __BackCompat => panic!("`HashType::__BackCompat` is not an actual hash algorithm"),
+        }
+    }
+
+    /// The directions the gradient variants compare values in; `Gradient` and `DoubleGradient`
+    /// are horizontal, and horizontal and vertical.
+    fn gradient_directions(self) -> Option<GradientDirections> {
+        match self {
+            HashType::Gradient => GradientDirections::new(true, false, false, false),
+            HashType::DoubleGradient => GradientDirections::new(true, true, false, false),
+            HashType::VerticalGradient => GradientDirections::new(false, true, false, false),
+            HashType::DiagonalGradient => GradientDirections::new(false, false, true, false),
+            HashType::MultiGradient(directions) => Some(directions),
+            _ => None,
+        }
+    }
+}
+
+/// The grayscale values an algorithm runs on, at the precision of the original image.
+enum Values {
+    Bytes(Vec<u8>),
+    Wide(Vec<f32>),
+}
+
+/// Evaluate `$body` with `$values` bound to the `Vec` in a `Values`, whatever its type.
+macro_rules! with_values {
+    ($values_expr:expr, |$values:ident| $body:expr) => (
+        match $values_expr {
+            Values::Bytes($values) => $body,
+            Values::Wide($values) => $body,
         }
+    )
+}
+
+/// A grayscale value from `Values`.
+trait HashValue: Copy + PartialOrd {
+    /// The value of full intensity.
+    const MAX: f64;
+
+    fn to_f64(self) -> f64;
+
+    /// Scale to `[0, 1]`.
+    fn to_unit(self) -> f64 {
+        self.to_f64() / Self::MAX
     }
+
+    /// The threshold the Mean hash compares each value against.
+    fn mean(values: &[Self]) -> f64;
 }
 
-fn mean_hash<I: HashImage>(img: &I, hash_size: u32) -> BitVec {
-    let hash_values = prepare_image(img, hash_size, hash_size);
+impl HashValue for u8 {
+    const MAX: f64 = 255.0;
 
-    let mean = hash_values.iter().fold(0u32, |b, &a| a as u32 + b) 
-        / hash_values.len() as u32;
+    fn to_f64(self) -> f64 { self as f64 }
 
-    hash_values.into_iter().map(|x| x as u32 >= mean).collect()
+    /// Truncated to an integer, as the Mean hash has always done for 8-bit images.
+    fn mean(values: &[u8]) -> f64 {
+        (values.iter().fold(0u32, |b, &a| a as u32 + b) / values.len() as u32) as f64
+    }
 }
 
-const DCT_HASH_SIZE_MULTIPLIER: u32 = 4;
+impl HashValue for f32 {
+    const MAX: f64 = 1.0;
 
-fn dct_hash<I: HashImage>(img: &I, hash_size: u32, dct_2d_func: DCT2DFunc) -> BitVec {
-    let large_size = (hash_size * DCT_HASH_SIZE_MULTIPLIER) as usize;
+    fn to_f64(self) -> f64 { self as f64 }
+
+    fn mean(values: &[f32]) -> f64 {
+        values.iter().fold(0f64, |b, &a| a as f64 + b) / values.len() as f64
+    }
+}
+
+fn prepare_image<I: HashImage>(img: &I, width: u32, height: u32) -> Values {
+    let resized = img.grayscale().resize(width, height);
+
+    if resized.is_wide() {
+        Values::Wide(resized.to_normalized())
+    } else {
+        Values::Bytes(resized.to_bytes())
+    }
+}
+
+const DCT_HASH_SIZE_MULTIPLIER: u32 = 4;
 
+/// The grayscale values of `img` at the size its DCT is taken at, scaled to `[0, 1]`.
+fn dct_values<I: HashImage>(img: &I, hash_size: u32) -> Vec<f64> {
     // We take a bigger resize than fast_hash, 
     // then we only take the lowest corner of the DCT
-    let hash_values: Vec<_> = prepare_image(img, large_size as u32, large_size as u32)
-        .into_iter().map(|val| (val as f64) / 255.0).collect();
+    let large_size = hash_size * DCT_HASH_SIZE_MULTIPLIER;
 
-    let dct = dct_2d_func.call(&hash_values, large_size);
+    with_values!(prepare_image(img, large_size, large_size),
+        |values| values.into_iter().map(HashValue::to_unit).collect())
+}
+
+/// The lowest `hash_size * hash_size` coefficients of the DCT of `values` from `dct_values()`.
+fn dct_of_values<F>(values: &[f64], hash_size: u32, dct_2d_func: F) -> Vec<f64>
+where F: FnOnce(&[f64], Rowstride) -> Vec<f64> {
+    let large_size = (hash_size * DCT_HASH_SIZE_MULTIPLIER) as usize;
+
+    let dct = dct_2d_func(values, large_size);
 
     let original = (large_size, large_size);
     let new = (hash_size as usize, hash_size as usize);
 
-    let cropped_dct = crop_2d_dct(&dct, original, new);
+    crop_2d_dct(&dct, original, new)
+}
 
+/// Compare each of the (cropped) DCT coefficients to their mean.
+fn dct_bits(cropped_dct: &[f64]) -> BitVec {
     let mean = cropped_dct.iter().fold(0f64, |b, &a| a + b) 
         / cropped_dct.len() as f64;
 
-    cropped_dct.into_iter().map(|x| x >= mean).collect()
+    cropped_dct.iter().map(|&x| x >= mean).collect()
 }
 
 struct Columns<'a, T: 'a> {
@@ -353,6 +928,7 @@ struct Columns<'a, T: 'a> {
 }
 
 impl<'a, T: 'a> Columns<'a, T> {
+    #[allow(clippy::redundant_field_names)]
     #[inline(always)]
     fn from_slice(data: &'a [T], rowstride: usize) -> Self {
         Columns {
@@ -365,6 +941,7 @@ impl<'a, T: 'a> Columns<'a, T> {
 
 impl<'a, T: 'a> Iterator for Columns<'a, T> {
     type Item = Column<'a, T>;
+    #[allow(clippy::redundant_field_names)]
     #[inline(always)]
     fn next(&mut self) -> Option<Self::Item> {
         if self.curr < self.rowstride {
@@ -395,47 +972,18 @@ impl<'a, T: 'a> ops::Index<usize> for Column<'a, T> {
 
 /// The guts of the gradient hash, 
 /// separated so we can reuse them for both `Gradient` and `DoubleGradient`.
-fn gradient_hash_impl<I: ops::Index<usize, Output=u8> + ?Sized>(bytes: &I, len: u32, bitv: &mut BitVec) {
+fn gradient_hash_impl<T, I>(values: &I, len: u32, bitv: &mut BitVec)
+where T: PartialOrd, I: ops::Index<usize, Output=T> + ?Sized {
     let len = len as usize;
 
     for i in 1 .. len {
-        let this = &bytes[i];
-        let last = &bytes[i - 1];
+        let this = &values[i];
+        let last = &values[i - 1];
 
         bitv.push(last < this);
     }
 }
 
-fn gradient_hash<I: HashImage>(img: &I, hash_size: u32) -> BitVec {
-    // We have one extra pixel in width so we have `hash_size` comparisons per row.
-    let bytes = prepare_image(img, hash_size + 1, hash_size);
-    let mut bitv = BitVec::with_capacity((hash_size * hash_size) as usize);
-
-    for row in bytes.chunks((hash_size + 1) as usize) {
-        gradient_hash_impl(row, hash_size, &mut bitv); 
-    }
-
-    bitv
-}
-
-fn double_gradient_hash<I: HashImage>(img: &I, hash_size: u32) -> BitVec {
-    // We have one extra pixel in each dimension so we have `hash_size` comparisons.
-    let rowstride = hash_size + 1;
-    let bytes = prepare_image(img, rowstride, rowstride);
-    let mut bitv = BitVec::with_capacity((hash_size * hash_size * 2) as usize);
-
-    
-    for row in bytes.chunks(rowstride as usize) { 
-        gradient_hash_impl(row, rowstride, &mut bitv);
-    }
-
-    for column in Columns::from_slice(&bytes, rowstride as usize) {
-        gradient_hash_impl(&column, hash_size, &mut bitv);
-    }
-
-    bitv
-}
-
 /// A trait for describing an image that can be successfully hashed.
 ///
 /// Implement this for custom image types.
@@ -467,10 +1015,50 @@ pub trait HashImage {
     ///
     /// The iteration order is unspecified. Implementations should use whatever is optimum.
     fn foreach_pixel<F>(&self, iter_fn: F) where F: FnMut(u32, u32, &[u8]);
-}
 
-fn prepare_image<I: HashImage>(img: &I, width: u32, height: u32) -> Vec<u8> {
-    img.grayscale().resize(width, height).to_bytes()
+    /// Return `true` if this image has more than 8 bits of precision per channel.
+    ///
+    /// The hash algorithms then use `to_normalized()` and `foreach_pixel_normalized()` instead
+    /// of `to_bytes()` and `foreach_pixel()`, so that 16-bit and floating-point images
+    /// are hashed without first being truncated to 8 bits.
+    ///
+    /// Defaults to `false`.
+    fn is_wide(&self) -> bool {
+        false
+    }
+
+    /// Convert `self` to its channel values, scaled so `1.0` is full intensity.
+    ///
+    /// Only called if `is_wide()` returns `true`. Defaults to converting `to_bytes()`.
+    fn to_normalized(self) -> Vec<f32> where Self: Sized {
+        self.to_bytes().into_iter().map(|byte| byte as f32 / 255.0).collect()
+    }
+
+    /// Call `iter_fn` for each pixel in the image, passing `(x, y, [pixel data])`
+    /// with channel values scaled so `1.0` is full intensity.
+    ///
+    /// Only called if `is_wide()` returns `true`. Defaults to converting from `foreach_pixel()`.
+    fn foreach_pixel_normalized<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[f32]) {
+        let mut px_f32 = [0f32; 4];
+
+        self.foreach_pixel(|x, y, px| {
+            for (out, &channel) in px_f32.iter_mut().zip(px) {
+                *out = channel as f32 / 255.0;
+            }
+
+            iter_fn(x, y, &px_f32[..px.len()]);
+        });
+    }
+
+    /// Copy out the `width x height` region with its top left corner at `(x, y)`, which is
+    /// always within the image.
+    ///
+    /// Used to trim borders with `Hasher::trim_borders()`. Defaults to `None`, meaning the image
+    /// can't be cropped, in which case it is hashed whole.
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Self> where Self: Sized {
+        let _ = (x, y, width, height);
+        None
+    }
 }
 
 /// Crop the values off a 1D-packed 2D DCT
@@ -501,15 +1089,14 @@ mod test {
 
     use self::rand::{weak_rng, Rng};
 
-    use super::{DCT2DFunc, HashType, ImageHash};
+    use super::{DCT2DFunc, DctCache, Hasher, HashType, ImageHash};
 
-    type RgbaBuf = ImageBuffer<Rgba<u8>, Vec<u8>>;
+    pub type RgbaBuf = ImageBuffer<Rgba<u8>, Vec<u8>>;
 
-    fn gen_test_img(width: u32, height: u32) -> RgbaBuf {
+    pub fn gen_test_img(width: u32, height: u32) -> RgbaBuf {
         let len = (width * height * 4) as usize;
-        let mut buf = Vec::with_capacity(len);
-        unsafe { buf.set_len(len); } // We immediately fill the buffer.
-        weak_rng().fill_bytes(&mut *buf);
+        let mut buf = vec![0; len];
+        weak_rng().fill_bytes(&mut buf);
 
         ImageBuffer::from_raw(width, height, buf).unwrap()
     }
@@ -553,6 +1140,8 @@ mod test {
     test_hash_type!(Block, blockhash);
     test_hash_type!(Gradient, gradient);
     test_hash_type!(DoubleGradient, dbl_gradient);
+    test_hash_type!(VerticalGradient, vertical_gradient);
+    test_hash_type!(DiagonalGradient, diagonal_gradient);
     test_hash_type!(DCT, dct);
 
     #[test]
@@ -591,6 +1180,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::explicit_auto_deref)]
     fn base64_encoding_decoding() {
         let test_img = gen_test_img(1024, 1024);
         let hash1 = ImageHash::hash(&test_img, 32, HashType::Mean);
@@ -610,6 +1200,154 @@ mod test {
         };
     }
 
+    #[test]
+    fn wide_formats_match_8_bit() {
+        use image::{Rgb32FImage, Rgba};
+
+        let test_img = gen_test_img(128, 128);
+
+        let img_16: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_fn(128, 128, |x, y| {
+            let px = test_img.get_pixel(x, y);
+            Rgba([px[0], px[1], px[2], px[3]].map(|c| c as u16 * 257))
+        });
+
+        let img_f32 = Rgb32FImage::from_fn(128, 128, |x, y| {
+            let px = test_img.get_pixel(x, y);
+            image::Rgb([px[0], px[1], px[2]].map(|c| c as f32 / 255.0))
+        });
+
+        let img_rgb8 = image::DynamicImage::ImageRgba8(test_img.clone()).into_rgb8();
+
+        // Blockhash sees the same 8-bit channel values exactly.
+        assert_eq!(ImageHash::hash(&img_16, 8, HashType::Block), ImageHash::hash(&test_img, 8, HashType::Block));
+        assert_eq!(ImageHash::hash(&img_f32, 8, HashType::Block), ImageHash::hash(&img_rgb8, 8, HashType::Block));
+
+        // The others get luma computed at the wider precision, so allow for rounding.
+        for &hash_type in &[HashType::Mean, HashType::Gradient] {
+            let hash = ImageHash::hash(&test_img, 8, hash_type);
+            assert!(ImageHash::hash(&img_16, 8, hash_type).dist_ratio(&hash) < 0.1);
+
+            let hash = ImageHash::hash(&img_rgb8, 8, hash_type);
+            assert!(ImageHash::hash(&img_f32, 8, hash_type).dist_ratio(&hash) < 0.1);
+        }
+    }
+
+    #[test]
+    fn wide_formats_keep_precision() {
+        use image::{DynamicImage, Luma};
+
+        // Every value rounds to 0 at 8 bits.
+        let img: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_fn(128, 128, |x, _| Luma([x as u16]));
+        let expected: Vec<bool> = (0 .. 64).map(|i| i % 8 >= 4).collect();
+
+        for hash in &[ImageHash::hash(&img, 8, HashType::Mean),
+                      ImageHash::hash(&DynamicImage::ImageLuma16(img.clone()), 8, HashType::Mean)] {
+            assert_eq!(hash.bitv.iter().collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[cfg(feature = "rust-image")]
+    #[test]
+    fn exif_orientation_applied() {
+        use image::codecs::jpeg::JpegEncoder;
+        use image::imageops;
+
+        let img = ImageBuffer::from_fn(40, 24, |x, y| image::Rgb([x as u8 * 6, y as u8 * 10, 128]));
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg).encode_image(&img).unwrap();
+
+        // An APP1 segment with a big-endian EXIF IFD holding only Orientation = 6 (rotate 90° CW).
+        let exif: &[u8] = b"\xff\xe1\x00\x22Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+        let mut rotated_jpeg = jpeg[..2].to_vec();
+        rotated_jpeg.extend_from_slice(exif);
+        rotated_jpeg.extend_from_slice(&jpeg[2..]);
+
+        let hasher = Hasher::new(8, HashType::Gradient);
+        let upright = imageops::rotate90(&hasher.decode_bytes(&jpeg).unwrap());
+
+        assert_eq!(hasher.hash_bytes(&rotated_jpeg).unwrap(), hasher.hash_bytes(&jpeg).unwrap());
+
+        let hasher = hasher.exif_orientation(true);
+        assert_eq!(hasher.hash_bytes(&rotated_jpeg).unwrap(), hasher.hash(&upright));
+        assert_eq!(hasher.fingerprint(), "gradient:8:exif");
+    }
+
+    #[test]
+    fn trim_letterbox() {
+        use super::{CropRect, TrimBorders};
+
+        let content = gen_test_img(64, 36);
+        // Slightly noisy black bars above and below.
+        let letterboxed = ImageBuffer::from_fn(64, 56, |x, y| match y {
+            10 ..= 45 => *content.get_pixel(x, y - 10),
+            _ => Rgba([(x % 4) as u8, 0, (y % 3) as u8, 255]),
+        });
+
+        let hasher = Hasher::new(8, HashType::Gradient);
+        let trimming = hasher.clone().trim_borders(TrimBorders::new());
+
+        assert_eq!(trimming.hash_with_crop(&letterboxed),
+                   (hasher.hash(&content), CropRect { x: 0, y: 10, width: 64, height: 36 }));
+        assert_eq!(trimming.hash_with_crop(&content).1, CropRect::full((64, 36)));
+        assert_eq!(trimming.fingerprint(), "gradient:8:trim16-0.25");
+    }
+
+    #[test]
+    fn trim_keeps_flat_content_next_to_border() {
+        use super::{CropRect, TrimBorders};
+
+        let content = gen_test_img(64, 48);
+        // A black bar, then a flat blue band that is part of the picture.
+        let img = ImageBuffer::from_fn(64, 64, |x, y| match y {
+            0 ..= 7 => Rgba([0, 0, 0, 255]),
+            8 ..= 15 => Rgba([40, 80, 200, 255]),
+            _ => *content.get_pixel(x, y - 16),
+        });
+
+        assert_eq!(TrimBorders::new().detect(&img), CropRect { x: 0, y: 8, width: 64, height: 56 });
+    }
+
+    #[test]
+    fn hasher_dct_cache_keeps_all_sizes() {
+        let test_img = gen_test_img(256, 256);
+        let cache = DctCache::new();
+
+        for &size in &[8, 16, 32] {
+            let hasher = Hasher::new(size, HashType::DCT).dct_cache(cache.clone());
+            assert_eq!(hasher.hash(&test_img), ImageHash::hash(&test_img, size, HashType::DCT));
+        }
+
+        for &size in &[8, 16, 32] {
+            assert!(cache.get((size * ::DCT_HASH_SIZE_MULTIPLIER) as usize).is_some());
+        }
+    }
+
+    #[test]
+    fn global_dct_cache_only_keeps_precomputed() {
+        let test_img = gen_test_img(64, 64);
+
+        ImageHash::hash(&test_img, 7, HashType::DCT);
+        Hasher::new(7, HashType::DCT).hash(&test_img);
+        assert!(DctCache::global().get(7 * ::DCT_HASH_SIZE_MULTIPLIER as usize).is_none());
+
+        ::precompute_dct_matrix(9);
+        assert!(DctCache::global().get(9 * ::DCT_HASH_SIZE_MULTIPLIER as usize).is_some());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_hash_all_matches_sequential() {
+        let images: Vec<_> = (0 .. 16).map(|_| gen_test_img(128, 128)).collect();
+
+        for &hash_type in &[HashType::Gradient, HashType::DCT] {
+            let sequential: Vec<_> = images.iter()
+                .map(|img| ImageHash::hash(img, 8, hash_type))
+                .collect();
+
+            assert_eq!(::par::hash_all(&images, 8, hash_type), sequential);
+        }
+    }
+
     #[cfg(feature = "bench")]
     mod bench {
         use super::gen_test_img;
@@ -647,6 +1385,8 @@ mod test {
 
         #[bench]
         fn bench_dct_hash(b: &mut Bencher) {
+            // The global cache only keeps matrices from `precompute_dct_matrix()`, so once it is
+            // cleared every hash computes its matrix again.
             ::dct::clear_precomputed_matrix();
             bench_hash(b, HashType::DCT);
         }
@@ -719,6 +1459,31 @@ mod test {
             b.iter(|| ::dct::dct_2d(&test_vals[..], ROWSTRIDE));
         }
 
+        #[cfg(feature = "rayon")]
+        const BATCH_LEN: usize = 64;
+
+        #[cfg(feature = "rayon")]
+        #[bench]
+        fn bench_dct_hash_batch_seq(b: &mut Bencher) {
+            let images: Vec<_> = (0 .. BATCH_LEN)
+                .map(|_| gen_test_img(TEST_IMAGE_SIZE, TEST_IMAGE_SIZE)).collect();
+
+            ::precompute_dct_matrix(BENCH_HASH_SIZE);
+
+            b.iter(|| images.iter()
+                .map(|img| ImageHash::hash(img, BENCH_HASH_SIZE, HashType::DCT))
+                .collect::<Vec<_>>());
+        }
+
+        #[cfg(feature = "rayon")]
+        #[bench]
+        fn bench_dct_hash_batch_par(b: &mut Bencher) {
+            let images: Vec<_> = (0 .. BATCH_LEN)
+                .map(|_| gen_test_img(TEST_IMAGE_SIZE, TEST_IMAGE_SIZE)).collect();
+
+            b.iter(|| ::par::hash_all(&images, BENCH_HASH_SIZE, HashType::DCT));
+        }
+
         #[inline(never)]
         fn fill_rand(out: &mut [f64]) {
             let mut rng = thread_rng();
@@ -0,0 +1,113 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Batch hashing on the [rayon][1] thread pool.
+//!
+//! Every worker thread hashes with the same `Hasher`, so any state the hash algorithm keeps
+//! between images (currently the DCT matrix for `HashType::DCT`, in a `DctCache`) is computed
+//! once per size and shared read-only between all of them.
+//!
+//! Requires the `rayon` feature.
+//!
+//! [1]: https://github.com/rayon-rs/rayon
+use rayon::iter::plumbing::{Consumer, ProducerCallback, UnindexedConsumer};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use cache::HashCache;
+
+use {DctCache, Hasher, HashImage, HashType, ImageHash};
+
+use std::io;
+use std::path::Path;
+
+/// Hash every image in `images` in parallel, returning the hashes in the same order.
+///
+/// Equivalent to calling `ImageHash::hash(img, hash_size, hash_type)` for each image.
+/// The DCT matrix, if needed, is kept in a `DctCache` for this call only.
+pub fn hash_all<I: HashImage + Sync>(images: &[I], hash_size: u32, hash_type: HashType) -> Vec<ImageHash> {
+    hash_all_with(images, &Hasher::new(hash_size, hash_type).dct_cache(DctCache::new()))
+}
+
+/// Hash every image in `images` in parallel with `hasher`, returning the hashes in the same order.
+pub fn hash_all_with<I: HashImage + Sync>(images: &[I], hasher: &Hasher) -> Vec<ImageHash> {
+    images.par_iter().hash_images_with(hasher.clone()).collect()
+}
+
+/// Hash the files at `paths` in parallel, returning the results in the same order.
+///
+/// The parallel version of `cache::hash_files_cached()`: files are looked up in `cache`, and
+/// loaded and hashed if needed, on the thread pool, then the new hashes are added to `cache`.
+pub fn hash_files_cached<P, I, E, F>(paths: &[P], hasher: &Hasher, cache: &mut HashCache, load: F)
+    -> Vec<Result<ImageHash, E>>
+where P: AsRef<Path> + Sync, I: HashImage, E: From<io::Error> + Send, F: Fn(&Path) -> Result<I, E> + Sync {
+    let hashed: Vec<_> = {
+        let cache = &*cache;
+        paths.par_iter().map(|path| cache.get_or_load(hasher, path.as_ref(), &load)).collect()
+    };
+
+    hashed.into_iter().zip(paths)
+        .map(|(hashed, path)| cache.insert_hashed(hasher, path.as_ref(), hashed))
+        .collect()
+}
+
+/// Extension trait adding `.hash_images()` to parallel iterators over image references.
+pub trait ParHashExt<'a, I: HashImage + Sync + 'a>: ParallelIterator<Item = &'a I> {
+    /// Hash each image yielded by this iterator with the given size and algorithm.
+    ///
+    /// If the underlying iterator is indexed, so is the returned one, preserving order on
+    /// `collect()`.
+    fn hash_images(self, hash_size: u32, hash_type: HashType) -> HashImages<Self> {
+        self.hash_images_with(Hasher::new(hash_size, hash_type))
+    }
+
+    /// Hash each image yielded by this iterator with `hasher`.
+    fn hash_images_with(self, hasher: Hasher) -> HashImages<Self> {
+        HashImages {
+            base: self,
+            hasher,
+        }
+    }
+}
+
+impl<'a, I: HashImage + Sync + 'a, P: ParallelIterator<Item = &'a I>> ParHashExt<'a, I> for P {}
+
+/// Parallel iterator returned by `ParHashExt::hash_images()`.
+pub struct HashImages<P> {
+    base: P,
+    hasher: Hasher,
+}
+
+impl<'a, I, P> ParallelIterator for HashImages<P>
+where I: HashImage + Sync + 'a, P: ParallelIterator<Item = &'a I> {
+    type Item = ImageHash;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result where C: UnindexedConsumer<ImageHash> {
+        let hasher = self.hasher;
+        self.base.map(|img| hasher.hash(img)).drive_unindexed(consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        self.base.opt_len()
+    }
+}
+
+impl<'a, I, P> IndexedParallelIterator for HashImages<P>
+where I: HashImage + Sync + 'a, P: IndexedParallelIterator<Item = &'a I> {
+    fn drive<C>(self, consumer: C) -> C::Result where C: Consumer<ImageHash> {
+        let hasher = self.hasher;
+        self.base.map(|img| hasher.hash(img)).drive(consumer)
+    }
+
+    fn len(&self) -> usize {
+        self.base.len()
+    }
+
+    fn with_producer<CB>(self, callback: CB) -> CB::Output where CB: ProducerCallback<ImageHash> {
+        let hasher = self.hasher;
+        self.base.map(|img| hasher.hash(img)).with_producer(callback)
+    }
+}
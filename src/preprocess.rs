@@ -0,0 +1,350 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Steps run on the grayscale image before it is resized and hashed, added to a hasher with
+//! `Hasher::preprocess()`.
+//!
+//! With any steps set, the image is converted to a `GrayBuffer` at its full size, each step
+//! is run on it in order, and the result is resized by averaging the pixels of each area.
+//! Without any, images are hashed exactly as before.
+use super::HashImage;
+
+use raw::span;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// A grayscale image with values in `[0, 1]`, which preprocessing steps work on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrayBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<f32>,
+}
+
+impl GrayBuffer {
+    /// Wrap `width * height` values, packed by row.
+    ///
+    /// ## Panics
+    /// If `pixels` has a different length.
+    pub fn new(width: u32, height: u32, pixels: Vec<f32>) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize,
+                   "GrayBuffer of {} x {} pixels", width, height);
+
+        GrayBuffer { width, height, pixels }
+    }
+
+    /// Convert `img` to grayscale, at the full precision of the image.
+    pub fn from_image<I: HashImage>(img: &I) -> Self {
+        let gray = img.grayscale();
+        let (width, height) = gray.dimensions();
+        let mut pixels = vec![0f32; width as usize * height as usize];
+
+        {
+            let mut set = |x: u32, y: u32, val: f32| pixels[(y * width + x) as usize] = val;
+
+            if gray.is_wide() {
+                gray.foreach_pixel_normalized(|x, y, px| set(x, y, px[0]));
+            } else {
+                gray.foreach_pixel(|x, y, px| set(x, y, px[0] as f32 / 255.));
+            }
+        }
+
+        GrayBuffer { width, height, pixels }
+    }
+
+    /// The values, packed by row.
+    pub fn pixels(&self) -> &[f32] {
+        &self.pixels
+    }
+
+    /// The values, packed by row, for changing in place.
+    pub fn pixels_mut(&mut self) -> &mut [f32] {
+        &mut self.pixels
+    }
+
+    /// Run `steps` on this image, in order.
+    pub(crate) fn preprocessed(self, steps: &[Arc<dyn Preprocessor>]) -> Self {
+        steps.iter().fold(self, |img, step| step.process(img))
+    }
+}
+
+impl HashImage for GrayBuffer {
+    type Grayscale = GrayBuffer;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Averages the pixels covered by each output pixel.
+    fn resize(&self, width: u32, height: u32) -> Self {
+        let (new_width, new_height) = (width as usize, height as usize);
+        let (old_width, old_height) = (self.width as usize, self.height as usize);
+
+        let x_spans: Vec<_> = (0 .. new_width).map(|x| span(x, new_width, old_width)).collect();
+        let mut out = Vec::with_capacity(new_width * new_height);
+
+        for y in 0 .. new_height {
+            let (y_start, y_end) = span(y, new_height, old_height);
+
+            for &(x_start, x_end) in &x_spans {
+                let sum: f64 = (y_start .. y_end)
+                    .flat_map(|y| &self.pixels[y * old_width + x_start .. y * old_width + x_end])
+                    .map(|&val| val as f64)
+                    .sum();
+
+                let count = (y_end - y_start) * (x_end - x_start);
+                out.push(if count == 0 { 0. } else { (sum / count as f64) as f32 });
+            }
+        }
+
+        GrayBuffer { width, height, pixels: out }
+    }
+
+    fn grayscale(&self) -> GrayBuffer {
+        self.clone()
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        self.pixels.into_iter().map(to_byte).collect()
+    }
+
+    fn channel_count() -> u8 {
+        1
+    }
+
+    fn foreach_pixel<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[u8]) {
+        self.foreach_pixel_normalized(|x, y, px| iter_fn(x, y, &[to_byte(px[0])]));
+    }
+
+    fn is_wide(&self) -> bool {
+        true
+    }
+
+    fn to_normalized(self) -> Vec<f32> {
+        self.pixels
+    }
+
+    fn foreach_pixel_normalized<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[f32]) {
+        for (i, &val) in self.pixels.iter().enumerate() {
+            let i = i as u32;
+            iter_fn(i % self.width, i / self.width, &[val]);
+        }
+    }
+
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Self> {
+        let (start, end) = (x as usize, (x + width) as usize);
+        let pixels = (y .. y + height)
+            .flat_map(|row| {
+                let row = row as usize * self.width as usize;
+                self.pixels[row + start .. row + end].iter().cloned()
+            })
+            .collect();
+
+        Some(GrayBuffer { width, height, pixels })
+    }
+}
+
+fn to_byte(val: f32) -> u8 {
+    (val.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// One step of preprocessing, run on the full-size grayscale image before it is resized.
+///
+/// Implement this for custom steps.
+pub trait Preprocessor: fmt::Debug + Send + Sync {
+    /// Transform `img`, which may change its dimensions.
+    fn process(&self, img: GrayBuffer) -> GrayBuffer;
+
+    /// A short string identifying this step and its settings in `Hasher::fingerprint()`.
+    ///
+    /// Two steps with the same fingerprint must produce the same output.
+    fn fingerprint(&self) -> String;
+}
+
+/// Blurs the image with a Gaussian kernel, so fine detail and noise don't affect the hash.
+///
+/// Several published hashes (pHash among them) blur the image before downsampling it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GaussianBlur {
+    sigma: f32,
+}
+
+impl GaussianBlur {
+    /// Blur with a standard deviation of `sigma` pixels of the full-size image.
+    /// Zero or less disables blurring.
+    pub fn new(sigma: f32) -> Self {
+        GaussianBlur { sigma: sigma.max(0.) }
+    }
+}
+
+impl Preprocessor for GaussianBlur {
+    fn process(&self, img: GrayBuffer) -> GrayBuffer {
+        if self.sigma <= 0. || img.pixels.is_empty() {
+            return img;
+        }
+
+        let radius = (self.sigma * 3.).ceil() as isize;
+        let kernel: Vec<f32> = (-radius ..= radius)
+            .map(|i| (-(i * i) as f32 / (2. * self.sigma * self.sigma)).exp())
+            .collect();
+        let total: f32 = kernel.iter().sum();
+        let kernel: Vec<f32> = kernel.into_iter().map(|weight| weight / total).collect();
+
+        let (width, height) = (img.width as usize, img.height as usize);
+
+        // Repeat the pixels past the edges.
+        let blur = |pixels: &[f32], len: usize, stride: usize, count: usize, step: usize| {
+            let mut out = vec![0f32; pixels.len()];
+
+            for line in 0 .. count {
+                for i in 0 .. len {
+                    out[line * step + i * stride] = kernel.iter().enumerate()
+                        .map(|(k, weight)| {
+                            let src = (i as isize + k as isize - radius).clamp(0, len as isize - 1) as usize;
+                            pixels[line * step + src * stride] * weight
+                        })
+                        .sum();
+                }
+            }
+
+            out
+        };
+
+        let rows = blur(&img.pixels, width, 1, height, width);
+        let pixels = blur(&rows, height, width, width, 1);
+
+        GrayBuffer { pixels, .. img }
+    }
+
+    fn fingerprint(&self) -> String {
+        format!("blur{}", self.sigma)
+    }
+}
+
+/// Spreads the values evenly over the whole range by histogram equalisation, so images
+/// differing in exposure, contrast or gamma hash alike.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Equalize;
+
+impl Preprocessor for Equalize {
+    fn process(&self, mut img: GrayBuffer) -> GrayBuffer {
+        let bin = |val: f32| to_byte(val) as usize;
+
+        let mut cdf = [0usize; 256];
+
+        for &val in &img.pixels {
+            cdf[bin(val)] += 1;
+        }
+
+        for i in 1 .. 256 {
+            cdf[i] += cdf[i - 1];
+        }
+
+        let total = img.pixels.len();
+        let min = cdf.iter().cloned().find(|&count| count > 0).unwrap_or(0);
+
+        if total == min {
+            // A single value; nothing to spread.
+            return img;
+        }
+
+        for val in &mut img.pixels {
+            *val = (cdf[bin(*val)] - min) as f32 / (total - min) as f32;
+        }
+
+        img
+    }
+
+    fn fingerprint(&self) -> String {
+        "equalize".to_owned()
+    }
+}
+
+/// Stretches the values linearly so the `low` quantile becomes black and the `high` quantile
+/// white, clamping those beyond.
+///
+/// The default of `0` and `1` normalises the darkest pixel to black and the brightest to white.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContrastStretch {
+    low: f32,
+    high: f32,
+}
+
+impl Default for ContrastStretch {
+    fn default() -> Self {
+        ContrastStretch { low: 0., high: 1. }
+    }
+}
+
+impl ContrastStretch {
+    /// Stretch between the darkest and brightest pixels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stretch between the `low` and `high` quantiles, in `[0, 1]`, ignoring outliers.
+    /// `0.01` and `0.99` are a common choice.
+    pub fn quantiles(low: f32, high: f32) -> Self {
+        ContrastStretch { low: low.clamp(0., 1.), high: high.clamp(0., 1.) }
+    }
+}
+
+impl Preprocessor for ContrastStretch {
+    fn process(&self, mut img: GrayBuffer) -> GrayBuffer {
+        if img.pixels.is_empty() {
+            return img;
+        }
+
+        let mut sorted = img.pixels.clone();
+        sorted.sort_by(|l, r| l.total_cmp(r));
+
+        let quantile = |q: f32| sorted[((sorted.len() - 1) as f32 * q).round() as usize];
+        let (low, high) = (quantile(self.low), quantile(self.high));
+
+        if high <= low {
+            return img;
+        }
+
+        for val in &mut img.pixels {
+            *val = ((*val - low) / (high - low)).clamp(0., 1.);
+        }
+
+        img
+    }
+
+    fn fingerprint(&self) -> String {
+        format!("stretch{}-{}", self.low, self.high)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{Luma, GrayImage};
+
+    use super::*;
+
+    use {Hasher, HashType};
+
+    #[test]
+    fn equalize_ignores_gamma() {
+        let img = GrayImage::from_fn(64, 48, |x, y| {
+            let val = 128. + 100. * (x as f64 / 9.).sin() * (y as f64 / 7.).cos();
+            Luma([val as u8])
+        });
+        let dim = GrayImage::from_fn(64, 48, |x, y| {
+            Luma([((img.get_pixel(x, y)[0] as f64 / 255.).powf(2.2) * 255.) as u8])
+        });
+
+        let plain = Hasher::new(8, HashType::DCT);
+        let hasher = plain.clone().preprocess(GaussianBlur::new(0.5)).preprocess(Equalize);
+
+        assert_eq!(hasher.fingerprint(), "dct:8:blur0.5+equalize");
+        assert!(hasher.hash(&img).dist(&hasher.hash(&dim)) <= plain.hash(&img).dist(&plain.hash(&dim)));
+        assert!(hasher.hash(&img).dist(&hasher.hash(&dim)) <= 2);
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Measuring how much information a hash carries, and how reliable each of its bits is, from the
+//! same values it was computed from.
+use bit_vec::BitVec;
+
+use super::ImageHash;
+
+/// How much information a hash carries, from `Hasher::hash_with_quality()`.
+///
+/// Nearly uniform images, such as blank pages and solid fills, hash to bits decided by noise or
+/// by nothing at all, which collide with the hashes of every other such image. Check `score()`
+/// to leave them out when looking for duplicates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HashQuality {
+    /// How evenly the bits are split between 0 and 1: 1 if half are set, 0 if all are the same.
+    pub bit_balance: f32,
+    /// The mean difference between neighbouring pixels of the grayscale image at the size it
+    /// was hashed at, from 0 for a solid fill to 1 for a black and white checkerboard.
+    pub gradient_energy: f32,
+    /// The mean distance between the values the bits were decided from and the thresholds they
+    /// were compared against, in units of full intensity. Bits with small margins are the ones
+    /// noise or recompression flips.
+    ///
+    /// For `DCT`, the values are the coefficients other than the DC term, scaled so a
+    /// full-contrast pattern at one frequency has a coefficient of about 1.
+    pub margin: f32,
+}
+
+impl HashQuality {
+    /// A single score from 0 to 1: a measure of `gradient_energy` that is 0.5 at an energy of
+    /// 0.01 and approaches 1 above that, reduced if fewer than a quarter of the bits differ from
+    /// the rest (`bit_balance` below 0.5; `DCT` hashes often come close).
+    ///
+    /// Blank or nearly uniform images score below about 0.25; photos usually above 0.5.
+    pub fn score(&self) -> f32 {
+        (2. * self.bit_balance).min(1.) * self.gradient_energy / (self.gradient_energy + 0.01)
+    }
+
+    pub(crate) fn new(bits: &BitVec, grid: &[f64], width: usize, margin: f64) -> Self {
+        HashQuality {
+            bit_balance: bit_balance(bits),
+            gradient_energy: mean_abs(neighbor_diffs(grid, width)) as f32,
+            margin: margin as f32,
+        }
+    }
+
+    /// The quality of `bits`, concatenated from hashes of several planes with `qualities`.
+    pub(crate) fn merge(qualities: &[HashQuality], bits: &BitVec) -> Self {
+        let mean = |field: fn(&HashQuality) -> f32| {
+            qualities.iter().map(field).sum::<f32>() / qualities.len().max(1) as f32
+        };
+
+        HashQuality {
+            bit_balance: bit_balance(bits),
+            gradient_energy: mean(|quality| quality.gradient_energy),
+            margin: mean(|quality| quality.margin),
+        }
+    }
+}
+
+fn bit_balance(bits: &BitVec) -> f32 {
+    if bits.is_empty() {
+        return 0.;
+    }
+
+    let ones = bits.iter().filter(|&bit| bit).count();
+    1. - (2. * ones as f32 / bits.len() as f32 - 1.).abs()
+}
+
+/// The differences between horizontally and vertically adjacent values of a grid, all the
+/// horizontal ones first.
+fn neighbor_diffs(grid: &[f64], width: usize) -> impl Iterator<Item = f64> + '_ {
+    let height = grid.len() / width;
+
+    let horizontal = grid.chunks(width).flat_map(|row| row.windows(2).map(|pair| pair[1] - pair[0]));
+    let vertical = (0 .. width * height.saturating_sub(1)).map(move |i| grid[i + width] - grid[i]);
+
+    horizontal.chain(vertical)
+}
+
+pub(crate) fn mean_abs<I: Iterator<Item = f64>>(values: I) -> f64 {
+    let (sum, count) = values.fold((0., 0), |(sum, count), val| (sum + val.abs(), count + 1));
+    if count == 0 { 0. } else { sum / count as f64 }
+}
+
+/// A hash that keeps how far each bit was from flipping, from `Hasher::hash_soft()`.
+///
+/// Bits decided by values right at their threshold flip randomly under recompression or
+/// resizing; comparing soft hashes discounts them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoftHash {
+    /// The hash itself, as `Hasher::hash()` returns it.
+    pub hash: ImageHash,
+    /// For each bit, the distance between the value it was decided from and its threshold,
+    /// in the units of `HashQuality::margin`.
+    pub margins: Vec<f32>,
+}
+
+impl SoftHash {
+    /// How far each bit can be trusted, from 0 to 1: its margin relative to the mean margin of
+    /// this hash, so bits at least as far from their threshold as average count fully.
+    pub fn confidences(&self) -> Vec<f32> {
+        let mean = self.margins.iter().sum::<f32>() / self.margins.len().max(1) as f32;
+
+        self.margins.iter()
+            .map(|&margin| if mean > 0. { (margin / mean).min(1.) } else { 0. })
+            .collect()
+    }
+
+    /// The Hamming distance to `other`, each differing bit counting by the lower of its
+    /// confidences in the two hashes.
+    ///
+    /// Two copies of an image differ mostly in low-confidence bits, so this separates copies
+    /// from different images better than `ImageHash::dist()`.
+    pub fn weighted_dist(&self, other: &SoftHash) -> f32 {
+        let differing = self.hash.differing_bits(&other.hash);
+
+        self.confidences().into_iter().zip(other.confidences()).zip(differing.iter())
+            .filter(|&(_, differs)| differs)
+            .map(|((left, right), _)| left.min(right))
+            .sum()
+    }
+
+    /// A mask of the bits with at least `min_confidence`, for indexing.
+    ///
+    /// Store it with the hash and compare new hashes against it with `ImageHash::dist_masked()`:
+    /// leaving out the bits that were close to flipping when the stored image was hashed
+    /// misses far fewer copies at the same distance threshold.
+    pub fn reliable_bits(&self, min_confidence: f32) -> BitVec {
+        self.confidences().into_iter().map(|confidence| confidence >= min_confidence).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::{weak_rng, Rng};
+
+    use image::{Luma, GrayImage};
+
+    use {Hasher, HashType};
+
+    #[test]
+    fn flags_blank_images() {
+        let mut rng = weak_rng();
+
+        // A blank page with a little sensor noise, and a pattern.
+        let blank = GrayImage::from_fn(64, 64, |_, _| Luma([200 + rng.gen_range(0, 2)]));
+        let pattern = GrayImage::from_fn(64, 64, |x, y| {
+            Luma([(128. + 100. * (x as f64 / 5.).sin() * (y as f64 / 8.).cos()) as u8])
+        });
+
+        for &hash_type in &[HashType::Mean, HashType::Block, HashType::Gradient, HashType::DoubleGradient,
+                            HashType::DCT] {
+            let hasher = Hasher::new(8, hash_type);
+
+            let (blank_hash, blank_quality) = hasher.hash_with_quality(&blank);
+            let soft = hasher.hash_soft(&pattern);
+
+            assert_eq!(soft.margins.len(), soft.hash.bitv.len(), "{:?}", hash_type);
+            assert_eq!(soft.weighted_dist(&soft), 0.);
+            let (pattern_hash, pattern_quality) = hasher.hash_with_quality(&pattern);
+
+            assert_eq!(blank_hash, hasher.hash(&blank));
+            assert_eq!(pattern_hash, hasher.hash(&pattern));
+
+            assert!(blank_quality.score() < 0.25, "{:?} {:?}", hash_type, blank_quality);
+            assert!(pattern_quality.score() > 0.5, "{:?} {:?}", hash_type, pattern_quality);
+            assert!(blank_quality.margin < pattern_quality.margin, "{:?}", hash_type);
+        }
+    }
+
+    #[test]
+    fn reliable_bits_survive_noise() {
+        let mut rng = weak_rng();
+
+        let img = GrayImage::from_fn(96, 96, |x, y| {
+            Luma([(128. + 60. * (x as f64 / 11.).sin() + 40. * (y as f64 / 6.).cos()) as u8])
+        });
+        let noisy = GrayImage::from_fn(96, 96, |x, y| {
+            Luma([(img.get_pixel(x, y)[0] as i32 + rng.gen_range(-12, 13)).clamp(0, 255) as u8])
+        });
+
+        let hasher = Hasher::new(16, HashType::Mean);
+        let (soft, noisy_soft) = (hasher.hash_soft(&img), hasher.hash_soft(&noisy));
+        let mask = soft.reliable_bits(0.5);
+
+        let dist = soft.hash.dist(&noisy_soft.hash);
+        assert!(dist > 0);
+        assert!(soft.hash.dist_masked(&noisy_soft.hash, &mask) < dist);
+        assert!(soft.weighted_dist(&noisy_soft) < dist as f32);
+    }
+
+    #[test]
+    fn every_path_agrees() {
+        use image::{Rgba, RgbaImage};
+
+        use {AlphaPolicy, BlockhashMethod, Channels, DCT2DFunc, GradientDirections, GrayConversion, ImageHash};
+
+        let pixel = |x: u32, y: u32| Rgba([(x * 7 + y * 3) as u8, (x * y) as u8, ((x ^ y) * 5) as u8,
+                                           if (x + y) % 13 == 0 { 0 } else { 255 }]);
+        // Evenly divided into blocks, and not.
+        let images = [RgbaImage::from_fn(64, 48, pixel), RgbaImage::from_fn(50, 37, pixel)];
+
+        let hash_types = [HashType::Mean, HashType::Block, HashType::Gradient, HashType::DoubleGradient,
+                          HashType::VerticalGradient, HashType::DiagonalGradient,
+                          HashType::MultiGradient(GradientDirections::all()), HashType::DCT,
+                          HashType::UserDCT(DCT2DFunc(::dct::dct_2d))];
+
+        for img in &images {
+            for &hash_type in &hash_types {
+                let plain = Hasher::new(8, hash_type);
+                assert_eq!(plain.hash(img), ImageHash::hash(img, 8, hash_type), "{:?}", hash_type);
+
+                let hashers = [
+                    plain.clone(),
+                    plain.clone().per_channel(Channels::Rgb),
+                    plain.clone().grayscale(GrayConversion::Average),
+                    plain.clone().alpha(AlphaPolicy::Composite([255, 255, 255])),
+                    plain.clone().blockhash_method(BlockhashMethod::Quick),
+                    plain.clone().blockhash_method(BlockhashMethod::Precise),
+                ];
+
+                for hasher in &hashers {
+                    let hash = hasher.hash(img);
+                    assert_eq!(hasher.hash_with_quality(img).0, hash, "{}", hasher.fingerprint());
+                    assert_eq!(hasher.hash_soft(img).hash, hash, "{}", hasher.fingerprint());
+                    assert_eq!(hasher.hash_dihedral(img).get(::Orientation::Identity), &hash,
+                               "{}", hasher.fingerprint());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Emitting several bits per cell, from which side of its threshold the value of the cell is on
+//! and how far from it.
+//!
+//! Each cell gets a level: the lower half of the levels for cells whose one-bit hash is `0`, the
+//! upper half for `1`, further from the middle the larger its margin is compared to the other
+//! cells of the hash. Levels are written as Gray codes, so the first bit of each cell is its
+//! one-bit hash and neighbouring levels differ in a single bit.
+use bit_vec::BitVec;
+
+/// The bits of the levels of cells with one-bit hash `bits` and `margins`.
+pub(crate) fn quantize(bits: &BitVec, margins: &[f32], bits_per_cell: u8) -> BitVec {
+    if margins.is_empty() {
+        return bits.clone();
+    }
+
+    let half = 1usize << (bits_per_cell - 1);
+
+    // The margins splitting the cells on either side of the threshold into `half` bins each.
+    let mut sorted = margins.to_vec();
+    sorted.sort_by(|l, r| l.total_cmp(r));
+    let edges: Vec<f32> = (1 .. half).map(|bin| sorted[bin * sorted.len() / half]).collect();
+
+    let mut out = BitVec::with_capacity(bits.len() * bits_per_cell as usize);
+
+    for (bit, &margin) in bits.iter().zip(margins) {
+        let bin = edges.iter().filter(|&&edge| margin >= edge).count();
+        let level = if bit { half + bin } else { half - 1 - bin };
+        let gray = level ^ (level >> 1);
+
+        for i in (0 .. bits_per_cell).rev() {
+            out.push(gray >> i & 1 == 1);
+        }
+    }
+
+    out
+}
+
+/// The sum of the differences between the levels of the cells of `left` and `right`.
+pub(crate) fn level_dist(left: &BitVec, right: &BitVec, bits_per_cell: u8) -> usize {
+    let levels = |bits: &BitVec| -> Vec<usize> {
+        let bits: Vec<bool> = bits.iter().collect();
+
+        bits.chunks(bits_per_cell as usize)
+            .map(|cell| {
+                // Each bit of the level is the XOR of the Gray code bits up to it.
+                let mut level = 0;
+                let mut prev = false;
+
+                for &bit in cell {
+                    prev ^= bit;
+                    level = level << 1 | prev as usize;
+                }
+
+                level
+            })
+            .collect()
+    };
+
+    levels(left).into_iter().zip(levels(right))
+        .map(|(left, right)| left.abs_diff(right))
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::{weak_rng, Rng};
+
+    use image::{GrayImage, Luma};
+
+    use {Hasher, HashType};
+
+    #[test]
+    fn levels_refine_bits() {
+        let mut rng = weak_rng();
+
+        let pattern = |phase: f64| GrayImage::from_fn(64, 64, move |x, y| {
+            Luma([(128. + 80. * ((x as f64 + phase) / 9.).sin() * (y as f64 / 13.).cos()) as u8])
+        });
+        let (img, other) = (pattern(0.), pattern(20.));
+        let noisy = GrayImage::from_fn(64, 64, |x, y| {
+            Luma([(img.get_pixel(x, y)[0] as i32 + rng.gen_range(-6, 7)).clamp(0, 255) as u8])
+        });
+
+        for &hash_type in &[HashType::Mean, HashType::Gradient, HashType::DCT] {
+            let plain = Hasher::new(8, hash_type);
+            let hasher = plain.clone().quantize(2);
+
+            let hash = hasher.hash(&img);
+            assert_eq!(hash.bitv.len(), 2 * plain.hash(&img).bitv.len());
+
+            // The first bit of each cell is the one-bit hash.
+            let first: ::bit_vec::BitVec = hash.bitv.iter().step_by(2).collect();
+            assert_eq!(first, plain.hash(&img).bitv, "{:?}", hash_type);
+
+            let near = hash.quantized_dist(&hasher.hash(&noisy), 2);
+            let far = hash.quantized_dist(&hasher.hash(&other), 2);
+            assert!(near < far, "{:?}: {} >= {}", hash_type, near, far);
+        }
+
+        assert_eq!(Hasher::new(8, HashType::Mean).quantize(3).fingerprint(), "mean:8:quantize3");
+    }
+
+    #[test]
+    fn edge_cases() {
+        use bit_vec::BitVec;
+
+        assert_eq!(super::quantize(&BitVec::new(), &[], 2), BitVec::new());
+
+        assert_eq!(Hasher::new(8, HashType::Mean).quantize(0).fingerprint(), "mean:8");
+        assert_eq!(Hasher::new(8, HashType::Mean).quantize(9).fingerprint(), "mean:8:quantize8");
+    }
+}
@@ -0,0 +1,533 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Hashing of raw, already-decoded pixel buffers without the `image` crate.
+use super::HashImage;
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+/// The layout of the pixels in a `RawImage`, all with 8 bits per channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// One luma byte per pixel.
+    Gray8,
+    /// Luma, then alpha.
+    GrayAlpha8,
+    /// Red, green, blue.
+    Rgb8,
+    /// Red, green, blue, alpha.
+    Rgba8,
+    /// Blue, green, red.
+    Bgr8,
+    /// Blue, green, red, alpha.
+    Bgra8,
+}
+
+impl PixelFormat {
+    /// The number of bytes each pixel takes up.
+    pub fn bytes_per_pixel(self) -> usize {
+        use self::PixelFormat::*;
+
+        match self {
+            Gray8 => 1,
+            GrayAlpha8 => 2,
+            Rgb8 | Bgr8 => 3,
+            Rgba8 | Bgra8 => 4,
+        }
+    }
+
+    /// Convert one pixel to RGBA, with alpha `0xff` for formats without alpha.
+    #[inline(always)]
+    fn to_rgba(self, px: &[u8]) -> [u8; 4] {
+        use self::PixelFormat::*;
+
+        match self {
+            Gray8 => [px[0], px[0], px[0], 0xff],
+            GrayAlpha8 => [px[0], px[0], px[0], px[1]],
+            Rgb8 => [px[0], px[1], px[2], 0xff],
+            Rgba8 => [px[0], px[1], px[2], px[3]],
+            Bgr8 => [px[2], px[1], px[0], 0xff],
+            Bgra8 => [px[2], px[1], px[0], px[3]],
+        }
+    }
+}
+
+/// The weights of the red, green and blue channels when converting a `RawImage` to grayscale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LumaWeights {
+    /// ITU-R BT.601, as used by standard-definition video and JPEG.
+    Bt601,
+    /// ITU-R BT.709, as used by high-definition video and sRGB. The default,
+    /// and the weights the `image` crate uses.
+    Bt709,
+}
+
+impl LumaWeights {
+    /// The weights of red, green and blue, in units of 1/10000.
+    fn weights(self) -> [u32; 3] {
+        match self {
+            LumaWeights::Bt601 => [2990, 5870, 1140],
+            LumaWeights::Bt709 => [2126, 7152, 722],
+        }
+    }
+
+    /// The weights of red, green and blue, summing to 1.
+    pub(crate) fn coefficients(self) -> [f32; 3] {
+        let [wr, wg, wb] = self.weights();
+        [wr as f32 / 10000., wg as f32 / 10000., wb as f32 / 10000.]
+    }
+
+    /// The luma of an RGB pixel, rounded to nearest.
+    #[inline(always)]
+    pub(crate) fn luma(self, r: u8, g: u8, b: u8) -> u8 {
+        let [wr, wg, wb] = self.weights();
+        ((r as u32 * wr + g as u32 * wg + b as u32 * wb + 5000) / 10000) as u8
+    }
+}
+
+/// The error returned when a buffer cannot hold a `RawImage` of the given dimensions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RawImageError {
+    /// The stride was shorter than a row of pixels.
+    StrideTooSmall {
+        /// The number of bytes a row of pixels takes up.
+        row_len: usize,
+        /// The stride that was given.
+        stride: usize,
+    },
+    /// The buffer was shorter than `stride * (height - 1) + row length`.
+    BufferTooSmall {
+        /// The number of bytes the image needs.
+        expected: usize,
+        /// The length of the buffer that was given.
+        actual: usize,
+    },
+}
+
+impl fmt::Display for RawImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RawImageError::StrideTooSmall { row_len, stride } =>
+                write!(f, "stride of {} bytes is shorter than a row of {} bytes", stride, row_len),
+            RawImageError::BufferTooSmall { expected, actual } =>
+                write!(f, "buffer of {} bytes is too small for image of {} bytes", actual, expected),
+        }
+    }
+}
+
+impl Error for RawImageError {}
+
+/// A view of a decoded image in memory, such as a frame from a video decoder,
+/// that can be hashed without any dependencies.
+///
+/// Rows may be padded (i.e. have a stride longer than `width * bytes_per_pixel`).
+/// Grayscale conversion uses the `LumaWeights` set with `luma_weights()` (BT.709 by default)
+/// and resizing averages the pixels covered by each output pixel.
+///
+/// Blockhash sees every format as RGBA.
+#[derive(Clone, Debug)]
+pub struct RawImage<'a> {
+    data: Cow<'a, [u8]>,
+    width: u32,
+    height: u32,
+    stride: usize,
+    format: PixelFormat,
+    weights: LumaWeights,
+}
+
+impl<'a> RawImage<'a> {
+    /// Wrap `data`, holding `height` rows of `width` pixels in `format` with no padding.
+    pub fn new(data: &'a [u8], width: u32, height: u32, format: PixelFormat) -> Result<Self, RawImageError> {
+        Self::with_stride(data, width, height, width as usize * format.bytes_per_pixel(), format)
+    }
+
+    /// Wrap `data`, holding `height` rows of `width` pixels in `format`, each row starting
+    /// `stride` bytes after the last.
+    pub fn with_stride(data: &'a [u8], width: u32, height: u32, stride: usize, format: PixelFormat)
+        -> Result<Self, RawImageError> {
+        Self::from_cow(Cow::Borrowed(data), width, height, stride, format)
+    }
+
+    pub(crate) fn from_cow(data: Cow<'a, [u8]>, width: u32, height: u32, stride: usize, format: PixelFormat)
+        -> Result<Self, RawImageError> {
+        let row_len = width as usize * format.bytes_per_pixel();
+
+        if stride < row_len {
+            return Err(RawImageError::StrideTooSmall { row_len, stride });
+        }
+
+        let expected = if height == 0 { 0 } else { stride * (height as usize - 1) + row_len };
+
+        if data.len() < expected {
+            return Err(RawImageError::BufferTooSmall { expected, actual: data.len() });
+        }
+
+        Ok(RawImage {
+            data,
+            width,
+            height,
+            stride,
+            format,
+            weights: LumaWeights::Bt709,
+        })
+    }
+
+    /// Set the weights used to convert color formats to grayscale.
+    pub fn luma_weights(self, weights: LumaWeights) -> Self {
+        RawImage { weights, .. self }
+    }
+
+    /// Get the pixel format of this image.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Get the pixel data of row `y`, without padding.
+    fn row(&self, y: usize) -> &[u8] {
+        let start = y * self.stride;
+        &self.data[start .. start + self.width as usize * self.format.bytes_per_pixel()]
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        (0 .. self.height as usize).map(move |y| self.row(y))
+    }
+
+    /// Copy the pixel data out without padding.
+    fn to_packed(&self) -> Vec<u8> {
+        self.rows().flat_map(|row| row.iter().cloned()).collect()
+    }
+
+    fn with_data(&self, data: Vec<u8>, width: u32, height: u32, format: PixelFormat) -> RawImage<'a> {
+        RawImage {
+            data: Cow::Owned(data),
+            width,
+            height,
+            stride: width as usize * format.bytes_per_pixel(),
+            format,
+            weights: self.weights,
+        }
+    }
+}
+
+/// The range of source pixels covered by output pixel `i` when resizing `old_len` to `new_len`.
+#[inline(always)]
+pub(crate) fn span(i: usize, new_len: usize, old_len: usize) -> (usize, usize) {
+    let start = i * old_len / new_len;
+    let end = ((i + 1) * old_len / new_len).max(start + 1).min(old_len);
+    (start, end)
+}
+
+impl<'a> HashImage for RawImage<'a> {
+    type Grayscale = RawImage<'a>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn resize(&self, width: u32, height: u32) -> Self {
+        let bpp = self.format.bytes_per_pixel();
+        let (new_width, new_height) = (width as usize, height as usize);
+        let (old_width, old_height) = (self.width as usize, self.height as usize);
+
+        let x_spans: Vec<_> = (0 .. new_width).map(|x| span(x, new_width, old_width)).collect();
+        let mut out = Vec::with_capacity(new_width * new_height * bpp);
+
+        for y in 0 .. new_height {
+            let (y_start, y_end) = span(y, new_height, old_height);
+
+            for &(x_start, x_end) in &x_spans {
+                let mut sums = [0u64; 4];
+
+                for row in (y_start .. y_end).map(|y| self.row(y)) {
+                    for px in row[x_start * bpp .. x_end * bpp].chunks(bpp) {
+                        for (sum, &channel) in sums.iter_mut().zip(px) {
+                            *sum += channel as u64;
+                        }
+                    }
+                }
+
+                let count = ((y_end - y_start) * (x_end - x_start)) as u64;
+
+                for &sum in &sums[..bpp] {
+                    out.push((sum + count / 2).checked_div(count).unwrap_or(0) as u8);
+                }
+            }
+        }
+
+        self.with_data(out, width, height, self.format)
+    }
+
+    fn grayscale(&self) -> RawImage<'a> {
+        if self.format == PixelFormat::Gray8 {
+            // No conversion needed; keep borrowing if we were.
+            let data = match self.data {
+                Cow::Borrowed(data) => Cow::Borrowed(data),
+                Cow::Owned(ref data) => Cow::Owned(data.clone()),
+            };
+
+            return RawImage { data, .. *self };
+        }
+
+        let bpp = self.format.bytes_per_pixel();
+
+        let luma = self.rows()
+            .flat_map(|row| row.chunks(bpp))
+            .map(|px| {
+                let [r, g, b, _] = self.format.to_rgba(px);
+                self.weights.luma(r, g, b)
+            })
+            .collect();
+
+        self.with_data(luma, self.width, self.height, PixelFormat::Gray8)
+    }
+
+    /// Returns the pixels in the image's own format, without row padding.
+    fn to_bytes(self) -> Vec<u8> {
+        match self.data {
+            Cow::Owned(data) if self.stride == self.width as usize * self.format.bytes_per_pixel() => {
+                let mut data = data;
+                data.truncate(self.stride * self.height as usize);
+                data
+            },
+            _ => self.to_packed(),
+        }
+    }
+
+    fn channel_count() -> u8 {
+        4
+    }
+
+    fn foreach_pixel<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[u8]) {
+        let bpp = self.format.bytes_per_pixel();
+
+        for (y, row) in self.rows().enumerate() {
+            for (x, px) in row.chunks(bpp).enumerate() {
+                iter_fn(x as u32, y as u32, &self.format.to_rgba(px));
+            }
+        }
+    }
+
+    /// Borrows the same buffer if this image does, otherwise copies the region out.
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Self> {
+        let bpp = self.format.bytes_per_pixel();
+
+        if let Cow::Borrowed(data) = self.data {
+            let data = Cow::Borrowed(&data[y as usize * self.stride + x as usize * bpp ..]);
+            return Some(RawImage { data, width, height, .. *self });
+        }
+
+        let (start, end) = (x as usize * bpp, (x + width) as usize * bpp);
+        let cropped = (y .. y + height)
+            .flat_map(|row| self.row(row as usize)[start .. end].iter().cloned())
+            .collect();
+
+        Some(self.with_data(cropped, width, height, self.format))
+    }
+}
+
+/// The layout of a YUV 4:2:0 video frame, as delivered by most decoders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum YuvFormat {
+    /// Planar: the Y plane, then the U and V planes at half the width and height (also I420).
+    Yuv420p,
+    /// Semi-planar: the Y plane, then one plane of interleaved U and V samples at half the height.
+    Nv12,
+    /// As `Nv12`, but with V before U.
+    Nv21,
+}
+
+impl YuvFormat {
+    /// The number of bytes a whole frame takes up when its Y plane has the given stride.
+    ///
+    /// The chroma planes are assumed to follow the Y plane directly, with half its stride for
+    /// `Yuv420p` and the same stride for `Nv12` and `Nv21`.
+    pub fn frame_len(self, height: u32, stride: usize) -> usize {
+        let luma_len = stride * height as usize;
+        let chroma_rows = (height as usize + 1) / 2;
+
+        luma_len + match self {
+            YuvFormat::Yuv420p => 2 * ((stride + 1) / 2) * chroma_rows,
+            YuvFormat::Nv12 | YuvFormat::Nv21 => stride * chroma_rows,
+        }
+    }
+}
+
+/// A YUV video frame, hashed by its Y (luma) plane without any conversion to RGB.
+///
+/// The hashes are the same as for a `RawImage` of the Y plane in `PixelFormat::Gray8`.
+/// Blockhash sees the frame as gray.
+#[derive(Clone, Debug)]
+pub struct YuvImage<'a> {
+    luma: RawImage<'a>,
+    format: YuvFormat,
+}
+
+impl<'a> YuvImage<'a> {
+    /// Wrap a whole frame in `format` with no padding between rows.
+    pub fn new(frame: &'a [u8], width: u32, height: u32, format: YuvFormat) -> Result<Self, RawImageError> {
+        Self::with_stride(frame, width, height, width as usize, format)
+    }
+
+    /// Wrap a whole frame in `format` whose Y plane rows start `stride` bytes after each other.
+    pub fn with_stride(frame: &'a [u8], width: u32, height: u32, stride: usize, format: YuvFormat)
+        -> Result<Self, RawImageError> {
+        let image = Self::from_y_plane(frame, width, height, stride, format)?;
+        let expected = format.frame_len(height, stride);
+
+        if frame.len() < expected {
+            return Err(RawImageError::BufferTooSmall { expected, actual: frame.len() });
+        }
+
+        Ok(image)
+    }
+
+    /// Wrap only the Y plane of a frame in `format`, for decoders that hand out each plane
+    /// separately. The chroma planes are never read.
+    pub fn from_y_plane(y_plane: &'a [u8], width: u32, height: u32, stride: usize, format: YuvFormat)
+        -> Result<Self, RawImageError> {
+        Ok(YuvImage {
+            luma: RawImage::with_stride(y_plane, width, height, stride, PixelFormat::Gray8)?,
+            format,
+        })
+    }
+
+    /// Get the format of the frame this was created from.
+    pub fn format(&self) -> YuvFormat {
+        self.format
+    }
+
+    /// Get the Y plane as a grayscale `RawImage`.
+    pub fn y_plane(&self) -> &RawImage<'a> {
+        &self.luma
+    }
+}
+
+impl<'a> HashImage for YuvImage<'a> {
+    type Grayscale = RawImage<'a>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.luma.dimensions()
+    }
+
+    /// Only the Y plane is resized; the chroma planes are dropped.
+    fn resize(&self, width: u32, height: u32) -> Self {
+        YuvImage {
+            luma: self.luma.resize(width, height),
+            format: self.format,
+        }
+    }
+
+    fn grayscale(&self) -> RawImage<'a> {
+        self.luma.grayscale()
+    }
+
+    /// Returns the Y plane without row padding.
+    fn to_bytes(self) -> Vec<u8> {
+        self.luma.to_bytes()
+    }
+
+    fn channel_count() -> u8 {
+        RawImage::channel_count()
+    }
+
+    fn foreach_pixel<F>(&self, iter_fn: F) where F: FnMut(u32, u32, &[u8]) {
+        self.luma.foreach_pixel(iter_fn)
+    }
+
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Self> {
+        Some(YuvImage {
+            luma: self.luma.crop(x, y, width, height)?,
+            format: self.format,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use {HashType, ImageHash};
+
+    const WIDTH: u32 = 67;
+    const HEIGHT: u32 = 45;
+
+    fn test_pixel(x: u32, y: u32) -> [u8; 3] {
+        [(x * 3 + y) as u8, (x * y) as u8, (255 - x * 2) as u8]
+    }
+
+    #[test]
+    fn stride_and_channel_order() {
+        const STRIDE: usize = WIDTH as usize * 3 + 13;
+
+        let mut rgb = Vec::new();
+        let mut bgr_padded = vec![0xAA; STRIDE * HEIGHT as usize];
+
+        for y in 0 .. HEIGHT {
+            for x in 0 .. WIDTH {
+                let px = test_pixel(x, y);
+                rgb.extend_from_slice(&px);
+
+                let start = y as usize * STRIDE + x as usize * 3;
+                bgr_padded[start .. start + 3].copy_from_slice(&[px[2], px[1], px[0]]);
+            }
+        }
+
+        let rgb = RawImage::new(&rgb, WIDTH, HEIGHT, PixelFormat::Rgb8).unwrap();
+        let bgr = RawImage::with_stride(&bgr_padded, WIDTH, HEIGHT, STRIDE, PixelFormat::Bgr8).unwrap();
+
+        for &hash_type in &[HashType::Mean, HashType::Block, HashType::Gradient, HashType::DCT] {
+            assert_eq!(ImageHash::hash(&rgb, 8, hash_type), ImageHash::hash(&bgr, 8, hash_type));
+        }
+    }
+
+    #[test]
+    fn yuv_hashes_y_plane() {
+        let mut luma = Vec::new();
+
+        for y in 0 .. HEIGHT {
+            for x in 0 .. WIDTH {
+                let [r, g, b] = test_pixel(x, y);
+                luma.push(LumaWeights::Bt601.luma(r, g, b));
+            }
+        }
+
+        let gray = RawImage::new(&luma, WIDTH, HEIGHT, PixelFormat::Gray8).unwrap();
+
+        for &format in &[YuvFormat::Yuv420p, YuvFormat::Nv12, YuvFormat::Nv21] {
+            for &stride in &[WIDTH as usize, WIDTH as usize + 5] {
+                // Chroma and padding that would change the hashes if they were read.
+                let mut frame: Vec<u8> = (0 .. format.frame_len(HEIGHT, stride)).map(|i| (i * 37) as u8).collect();
+
+                for (y, row) in luma.chunks(WIDTH as usize).enumerate() {
+                    frame[y * stride .. y * stride + row.len()].copy_from_slice(row);
+                }
+
+                let yuv = YuvImage::with_stride(&frame, WIDTH, HEIGHT, stride, format).unwrap();
+                assert_eq!(yuv.grayscale().to_bytes(), luma, "{:?} {}", format, stride);
+
+                for &hash_type in &[HashType::Mean, HashType::Block, HashType::Gradient, HashType::DCT] {
+                    assert_eq!(ImageHash::hash(&gray, 8, hash_type), ImageHash::hash(&yuv, 8, hash_type),
+                               "{:?} {} {:?}", format, stride, hash_type);
+                }
+
+                assert!(YuvImage::with_stride(&frame[1 ..], WIDTH, HEIGHT, stride, format).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let data = vec![0; 10];
+
+        assert_eq!(RawImage::new(&data, 4, 4, PixelFormat::Gray8).unwrap_err(),
+                   RawImageError::BufferTooSmall { expected: 16, actual: 10 });
+        assert_eq!(RawImage::with_stride(&data, 4, 2, 3, PixelFormat::Gray8).unwrap_err(),
+                   RawImageError::StrideTooSmall { row_len: 4, stride: 3 });
+    }
+}
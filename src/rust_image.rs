@@ -1,30 +1,86 @@
-// Copyright (c) 2015-2017 The `img_hash` Crate Developers
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
 //
 // Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
 // http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
 // <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+//! `HashImage` impls for `image` 0.25, with the `rust-image` feature.
+use image::imageops::{self, FilterType};
 use image::{
-    imageops,
+    ColorType,
     DynamicImage,
-    FilterType,
+    GenericImageView,
     GrayImage,
-    GrayAlphaImage,
-    RgbImage,
-    RgbaImage,
-    GenericImage,
-    Pixel
+    ImageBuffer,
+    ImageDecoder,
+    ImageReader,
+    ImageResult,
+    Luma,
+    LumaA,
+    Pixel,
+    Rgb,
+    Rgba,
 };
 
-use super::HashImage;
+use super::{HashImage, Hasher, ImageHash};
+
+use std::io::{BufRead, Cursor, Seek};
+use std::path::Path;
 
 const FILTER_TYPE: FilterType = FilterType::Nearest;
 
+/// The subpixel types of `image` and how `HashImage` sees them.
+trait Channel: Copy {
+    /// `true` for types with more than 8 bits of precision.
+    const WIDE: bool;
+
+    fn to_u8(self) -> u8;
+
+    fn to_normalized(self) -> f32;
+}
+
+impl Channel for u8 {
+    const WIDE: bool = false;
+
+    #[inline(always)]
+    fn to_u8(self) -> u8 { self }
+
+    #[inline(always)]
+    fn to_normalized(self) -> f32 { self as f32 / 255.0 }
+}
+
+impl Channel for u16 {
+    const WIDE: bool = true;
+
+    #[inline(always)]
+    fn to_u8(self) -> u8 {
+        // Round to nearest, same as `image`'s own conversions.
+        ((self as u32 + 128) / 257) as u8
+    }
+
+    #[inline(always)]
+    fn to_normalized(self) -> f32 { self as f32 / 65535.0 }
+}
+
+impl Channel for f32 {
+    const WIDE: bool = true;
+
+    #[inline(always)]
+    fn to_u8(self) -> u8 {
+        // `NaN` saturates to 0.
+        (self.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+
+    /// Left as-is, so HDR values above `1.0` are kept.
+    #[inline(always)]
+    fn to_normalized(self) -> f32 { self }
+}
+
 macro_rules! hash_img_impl {
-    ($ty:ident ($lumaty:ty)) => (
-        impl HashImage for $ty {
-            type Grayscale = $lumaty;
+    ($pixel:ident<$subpx:ty>) => (
+        impl HashImage for ImageBuffer<$pixel<$subpx>, Vec<$subpx>> {
+            type Grayscale = ImageBuffer<Luma<$subpx>, Vec<$subpx>>;
 
             fn dimensions(&self) -> (u32, u32) {
                 self.dimensions()
@@ -34,59 +90,234 @@ macro_rules! hash_img_impl {
                 imageops::resize(self, width, height, FILTER_TYPE)
             }
 
-            fn grayscale(&self) -> $lumaty {
+            fn grayscale(&self) -> Self::Grayscale {
                 imageops::grayscale(self)
             }
 
             fn to_bytes(self) -> Vec<u8> {
-                self.into_raw()
+                self.into_raw().into_iter().map(Channel::to_u8).collect()
             }
 
             fn channel_count() -> u8 {
-                <<Self as GenericImage>::Pixel as Pixel>::channel_count()
+                <$pixel<$subpx> as Pixel>::CHANNEL_COUNT
             }
 
             fn foreach_pixel<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[u8]) {
+                let mut px_u8 = [0u8; 4];
+
+                for (x, y, px) in self.enumerate_pixels() {
+                    let channels = px.channels();
+
+                    for (out, &channel) in px_u8.iter_mut().zip(channels) {
+                        *out = channel.to_u8();
+                    }
+
+                    iter_fn(x, y, &px_u8[..channels.len()]);
+                }
+            }
+
+            fn is_wide(&self) -> bool {
+                <$subpx as Channel>::WIDE
+            }
+
+            fn to_normalized(self) -> Vec<f32> {
+                self.into_raw().into_iter().map(Channel::to_normalized).collect()
+            }
+
+            fn foreach_pixel_normalized<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[f32]) {
+                let mut px_f32 = [0f32; 4];
+
                 for (x, y, px) in self.enumerate_pixels() {
-                    iter_fn(x, y, px.channels());
+                    let channels = px.channels();
+
+                    for (out, &channel) in px_f32.iter_mut().zip(channels) {
+                        *out = channel.to_normalized();
+                    }
+
+                    iter_fn(x, y, &px_f32[..channels.len()]);
                 }
             }
+
+            fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Self> {
+                Some(imageops::crop_imm(self, x, y, width, height).to_image())
+            }
         }
     );
-    ($($ty:ident ($lumaty:ty)),+) => ( $(hash_img_impl! { $ty($lumaty) })+ );
+    ($($pixel:ident<$subpx:ty>),+) => ( $(hash_img_impl! { $pixel<$subpx> })+ );
+}
+
+hash_img_impl! {
+    Luma<u8>, LumaA<u8>, Rgb<u8>, Rgba<u8>,
+    Luma<u16>, LumaA<u16>, Rgb<u16>, Rgba<u16>,
+    Luma<f32>, LumaA<f32>, Rgb<f32>, Rgba<f32>
+}
+
+/// The grayscale form of a `DynamicImage`, keeping the precision of the original.
+///
+/// 8-bit images become `Luma8`, 16-bit images `Luma16` and floating-point images `Luma32F`.
+#[derive(Clone, Debug)]
+pub enum DynamicGrayImage {
+    /// Grayscale with 8 bits per pixel.
+    Luma8(GrayImage),
+    /// Grayscale with 16 bits per pixel.
+    Luma16(ImageBuffer<Luma<u16>, Vec<u16>>),
+    /// Grayscale with a 32-bit float per pixel.
+    Luma32F(ImageBuffer<Luma<f32>, Vec<f32>>),
+}
+
+macro_rules! with_gray {
+    ($gray:expr, |$buf:ident| $body:expr) => (
+        match $gray {
+            DynamicGrayImage::Luma8($buf) => $body,
+            DynamicGrayImage::Luma16($buf) => $body,
+            DynamicGrayImage::Luma32F($buf) => $body,
+        }
+    )
 }
 
-hash_img_impl! { 
-    GrayImage(GrayImage), GrayAlphaImage(GrayImage), 
-    RgbImage(GrayImage), RgbaImage(GrayImage) 
+impl HashImage for DynamicGrayImage {
+    type Grayscale = Self;
+
+    fn dimensions(&self) -> (u32, u32) {
+        with_gray!(self, |buf| buf.dimensions())
+    }
+
+    fn resize(&self, width: u32, height: u32) -> Self {
+        match *self {
+            DynamicGrayImage::Luma8(ref buf) => DynamicGrayImage::Luma8(buf.resize(width, height)),
+            DynamicGrayImage::Luma16(ref buf) => DynamicGrayImage::Luma16(buf.resize(width, height)),
+            DynamicGrayImage::Luma32F(ref buf) => DynamicGrayImage::Luma32F(buf.resize(width, height)),
+        }
+    }
+
+    fn grayscale(&self) -> Self {
+        self.clone()
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        with_gray!(self, |buf| buf.to_bytes())
+    }
+
+    fn channel_count() -> u8 {
+        1
+    }
+
+    fn foreach_pixel<F>(&self, iter_fn: F) where F: FnMut(u32, u32, &[u8]) {
+        with_gray!(self, |buf| buf.foreach_pixel(iter_fn))
+    }
+
+    fn is_wide(&self) -> bool {
+        with_gray!(self, |buf| buf.is_wide())
+    }
+
+    fn to_normalized(self) -> Vec<f32> {
+        with_gray!(self, |buf| buf.to_normalized())
+    }
+
+    fn foreach_pixel_normalized<F>(&self, iter_fn: F) where F: FnMut(u32, u32, &[f32]) {
+        with_gray!(self, |buf| buf.foreach_pixel_normalized(iter_fn))
+    }
+
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Self> {
+        Some(match *self {
+            DynamicGrayImage::Luma8(ref buf) => DynamicGrayImage::Luma8(buf.crop(x, y, width, height)?),
+            DynamicGrayImage::Luma16(ref buf) => DynamicGrayImage::Luma16(buf.crop(x, y, width, height)?),
+            DynamicGrayImage::Luma32F(ref buf) => DynamicGrayImage::Luma32F(buf.crop(x, y, width, height)?),
+        })
+    }
 }
 
+/// Blockhash sees these as RGBA with 8 bits per channel, or with normalized `f32` channels
+/// if the image has 16-bit or floating-point channels.
+///
+/// The other algorithms hash the `DynamicGrayImage` of the same precision as the image.
 impl HashImage for DynamicImage {
-            type Grayscale = GrayImage;
+    type Grayscale = DynamicGrayImage;
 
-            fn dimensions(&self) -> (u32, u32) {
-                <Self as GenericImage>::dimensions(self) 
-            }
+    fn dimensions(&self) -> (u32, u32) {
+        <Self as GenericImageView>::dimensions(self)
+    }
 
-            fn resize(&self, width: u32, height: u32) -> Self {
-                self.resize(width, height, FILTER_TYPE)
-            }
+    fn resize(&self, width: u32, height: u32) -> Self {
+        self.resize_exact(width, height, FILTER_TYPE)
+    }
 
-            fn grayscale(&self) -> GrayImage {
-                imageops::grayscale(self)
-            }
+    fn grayscale(&self) -> DynamicGrayImage {
+        match self.color() {
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 =>
+                DynamicGrayImage::Luma16(self.to_luma16()),
+            ColorType::Rgb32F | ColorType::Rgba32F => DynamicGrayImage::Luma32F(self.to_luma32f()),
+            _ => DynamicGrayImage::Luma8(self.to_luma8()),
+        }
+    }
 
-            fn to_bytes(self) -> Vec<u8> {
-                self.raw_pixels()
-            }
+    fn to_bytes(self) -> Vec<u8> {
+        self.into_rgba8().into_raw()
+    }
 
-            fn channel_count() -> u8 {
-                <<Self as GenericImage>::Pixel as Pixel>::channel_count()
-            }
+    fn channel_count() -> u8 {
+        <Rgba<u8> as Pixel>::CHANNEL_COUNT
+    }
 
-            fn foreach_pixel<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[u8]) {
-                for (x, y, px) in self.pixels() {
-                    iter_fn(x, y, px.channels());
-                }
-            }
+    fn foreach_pixel<F>(&self, mut iter_fn: F) where F: FnMut(u32, u32, &[u8]) {
+        for (x, y, px) in self.pixels() {
+            iter_fn(x, y, px.channels());
+        }
+    }
+
+    fn is_wide(&self) -> bool {
+        self.color().bytes_per_pixel() > self.color().channel_count()
+    }
+
+    fn to_normalized(self) -> Vec<f32> {
+        self.into_rgba32f().into_raw()
+    }
+
+    fn foreach_pixel_normalized<F>(&self, iter_fn: F) where F: FnMut(u32, u32, &[f32]) {
+        self.to_rgba32f().foreach_pixel_normalized(iter_fn)
+    }
+
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Option<Self> {
+        Some(self.crop_imm(x, y, width, height))
+    }
+}
+
+/// Entry points that decode the image themselves, and so can see its metadata.
+impl Hasher {
+    /// Decode the image at `path`, applying its EXIF orientation if enabled with
+    /// `exif_orientation()`.
+    ///
+    /// The format is guessed from the file contents, falling back to the extension.
+    pub fn decode_path<P: AsRef<Path>>(&self, path: P) -> ImageResult<DynamicImage> {
+        self.decode(ImageReader::open(path)?)
+    }
+
+    /// Decode the image in `bytes`, applying its EXIF orientation if enabled with
+    /// `exif_orientation()`.
+    pub fn decode_bytes(&self, bytes: &[u8]) -> ImageResult<DynamicImage> {
+        self.decode(ImageReader::new(Cursor::new(bytes)))
+    }
+
+    /// Decode the image at `path` with `decode_path()` and hash it.
+    pub fn hash_path<P: AsRef<Path>>(&self, path: P) -> ImageResult<ImageHash> {
+        Ok(self.hash(&self.decode_path(path)?))
+    }
+
+    /// Decode the image in `bytes` with `decode_bytes()` and hash it.
+    pub fn hash_bytes(&self, bytes: &[u8]) -> ImageResult<ImageHash> {
+        Ok(self.hash(&self.decode_bytes(bytes)?))
+    }
+
+    fn decode<R: BufRead + Seek>(&self, reader: ImageReader<R>) -> ImageResult<DynamicImage> {
+        let mut decoder = reader.with_guessed_format()?.into_decoder()?;
+
+        let orientation = if self.exif_orientation { Some(decoder.orientation()?) } else { None };
+        let mut image = DynamicImage::from_decoder(decoder)?;
+
+        if let Some(orientation) = orientation {
+            image.apply_orientation(orientation);
         }
+
+        Ok(image)
+    }
+}
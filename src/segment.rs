@@ -0,0 +1,274 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Crop-resistant hashing: hashes of the separate regions of an image, which survive cropping
+//! as long as some of the regions do.
+//!
+//! Modelled on `crop_resistant_hash` from the Python [ImageHash][1] library, after
+//! [Efficient Cropping-Resistant Robust Image Hashing][2] (Jain et al., 2014): the image is
+//! blurred at a low resolution and split into connected regions of bright and dark pixels,
+//! then the bounding box of each large enough region is cropped out of the full-size image and
+//! hashed on its own.
+//!
+//! [1]: https://github.com/JohannesBuchner/imagehash
+//! [2]: https://ieeexplore.ieee.org/document/6980335
+use {CropRect, Hasher, HashImage, ImageHash, Values};
+
+/// Hashes each region of an image separately into a `MultiHash`.
+#[derive(Clone, Debug)]
+pub struct SegmentHasher {
+    hasher: Hasher,
+    segmentation_size: u32,
+    threshold: u8,
+    min_segment_size: usize,
+}
+
+impl SegmentHasher {
+    /// Create a `SegmentHasher` hashing the regions it finds with `hasher`.
+    ///
+    /// Segments at 300 x 300 pixels with a threshold of 128, keeping regions of more than
+    /// 500 pixels; the same defaults as ImageHash.
+    pub fn new(hasher: Hasher) -> Self {
+        SegmentHasher {
+            hasher,
+            segmentation_size: 300,
+            threshold: 128,
+            min_segment_size: 500,
+        }
+    }
+
+    /// Set the width and height the image is resized to for finding regions.
+    pub fn segmentation_size(self, segmentation_size: u32) -> Self {
+        SegmentHasher { segmentation_size, .. self }
+    }
+
+    /// Set the brightness, on a scale of 0 to 255, dividing bright regions from dark ones.
+    pub fn threshold(self, threshold: u8) -> Self {
+        SegmentHasher { threshold, .. self }
+    }
+
+    /// Set the number of pixels, at the segmentation size, a region needs more of to be hashed.
+    pub fn min_segment_size(self, min_segment_size: usize) -> Self {
+        SegmentHasher { min_segment_size, .. self }
+    }
+
+    /// Find the regions of `img` and hash each of them.
+    ///
+    /// If no region is large enough, or the image type does not support `HashImage::crop()`,
+    /// the whole image is hashed as a single segment.
+    pub fn hash<I: HashImage>(&self, img: &I) -> MultiHash {
+        let (width, height) = img.dimensions();
+        let full = CropRect::full((width, height));
+        let size = self.segmentation_size as usize;
+
+        let rects: Vec<CropRect> = if width == 0 || height == 0 || size == 0 {
+            vec![]
+        } else {
+            let pixels = median_3x3(&box_blur_3x3(&segmentation_pixels(img, self.segmentation_size), size), size);
+
+            find_segments(&pixels, size, self.threshold, self.min_segment_size).into_iter()
+                .map(|(min_x, min_y, max_x, max_y)| {
+                    let scale = |min: usize, max: usize, len: u32| {
+                        let start = min as u64 * len as u64 / size as u64;
+                        let end = ((max + 1) as u64 * len as u64 + size as u64 - 1) / size as u64;
+                        (start as u32, (end - start).max(1) as u32)
+                    };
+
+                    let (x, width) = scale(min_x, max_x, width);
+                    let (y, height) = scale(min_y, max_y, height);
+                    CropRect { x, y, width, height }
+                })
+                .collect()
+        };
+
+        let segments: Option<Vec<_>> = rects.into_iter()
+            .map(|rect| Some(Segment {
+                rect,
+                hash: self.hasher.hash(&img.crop(rect.x, rect.y, rect.width, rect.height)?),
+            }))
+            .collect();
+
+        match segments {
+            Some(segments) if !segments.is_empty() => MultiHash { segments },
+            _ => MultiHash {
+                segments: vec![Segment { rect: full, hash: self.hasher.hash(img) }],
+            },
+        }
+    }
+}
+
+/// The hash of one region of an image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// The bounding box of the region in the image.
+    pub rect: CropRect,
+    /// The hash of the image cropped to `rect`.
+    pub hash: ImageHash,
+}
+
+/// The hashes of all regions of an image, from `SegmentHasher::hash()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiHash {
+    /// The regions, bright ones first, then dark ones.
+    pub segments: Vec<Segment>,
+}
+
+impl MultiHash {
+    /// Count the segments of this image with a segment of `other` at most `max_dist` bits away,
+    /// and sum those distances (using the closest segment of `other` for each).
+    ///
+    /// Both must have been hashed with the same `Hasher` settings.
+    pub fn hash_diff(&self, other: &MultiHash, max_dist: usize) -> (usize, usize) {
+        self.segments.iter()
+            .filter_map(|segment| {
+                other.segments.iter().map(|theirs| segment.hash.dist(&theirs.hash)).min()
+            })
+            .filter(|&dist| dist <= max_dist)
+            .fold((0, 0), |(matches, total), dist| (matches + 1, total + dist))
+    }
+
+    /// Count the segments of this image with a segment of `other` at most `max_dist` bits away.
+    ///
+    /// A single match is usually enough to consider one image a crop of the other.
+    pub fn matches(&self, other: &MultiHash, max_dist: usize) -> usize {
+        self.hash_diff(other, max_dist).0
+    }
+}
+
+/// Get the grayscale values of `img` at `size x size`, on a scale of 0 to 255.
+fn segmentation_pixels<I: HashImage>(img: &I, size: u32) -> Vec<u8> {
+    match ::prepare_image(img, size, size) {
+        Values::Bytes(bytes) => bytes,
+        Values::Wide(values) => values.into_iter().map(|val| (val.clamp(0., 1.) * 255.).round() as u8).collect(),
+    }
+}
+
+/// Apply `filter` to the 3 x 3 neighbourhood of each pixel, repeating pixels past the edges.
+fn filter_3x3<F: Fn(&mut [u8; 9]) -> u8>(pixels: &[u8], size: usize, filter: F) -> Vec<u8> {
+    let clamp = |val: usize, delta: isize| (val as isize + delta).clamp(0, size as isize - 1) as usize;
+    let mut window = [0u8; 9];
+
+    (0 .. size * size).map(|i| {
+        let (x, y) = (i % size, i / size);
+
+        for (j, px) in window.iter_mut().enumerate() {
+            let (dx, dy) = (j as isize % 3 - 1, j as isize / 3 - 1);
+            *px = pixels[clamp(y, dy) * size + clamp(x, dx)];
+        }
+
+        filter(&mut window)
+    }).collect()
+}
+
+fn box_blur_3x3(pixels: &[u8], size: usize) -> Vec<u8> {
+    filter_3x3(pixels, size, |window| ((window.iter().map(|&px| px as u32).sum::<u32>() + 4) / 9) as u8)
+}
+
+fn median_3x3(pixels: &[u8], size: usize) -> Vec<u8> {
+    filter_3x3(pixels, size, |window| {
+        window.sort_unstable();
+        window[4]
+    })
+}
+
+/// Find the connected regions of pixels above `threshold`, then of those at or below it,
+/// returning the bounding boxes `(min_x, min_y, max_x, max_y)` of those with more than
+/// `min_size` pixels.
+fn find_segments(pixels: &[u8], size: usize, threshold: u8, min_size: usize) -> Vec<(usize, usize, usize, usize)> {
+    let mut assigned = vec![false; pixels.len()];
+    let mut segments = Vec::new();
+    let mut stack = Vec::new();
+
+    for &bright in &[true, false] {
+        let in_class = |idx: usize| (pixels[idx] > threshold) == bright;
+
+        for start in 0 .. pixels.len() {
+            if assigned[start] || !in_class(start) {
+                continue;
+            }
+
+            assigned[start] = true;
+            stack.push(start);
+
+            let (mut min_x, mut min_y, mut max_x, mut max_y) = (size, size, 0, 0);
+            let mut count = 0;
+
+            while let Some(idx) = stack.pop() {
+                let (x, y) = (idx % size, idx / size);
+
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+                count += 1;
+
+                let neighbors = [
+                    if x > 0 { Some(idx - 1) } else { None },
+                    if x + 1 < size { Some(idx + 1) } else { None },
+                    if y > 0 { Some(idx - size) } else { None },
+                    if y + 1 < size { Some(idx + size) } else { None },
+                ];
+
+                for neighbor in neighbors.iter().flatten().cloned() {
+                    if !assigned[neighbor] && in_class(neighbor) {
+                        assigned[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+
+            if count > min_size {
+                segments.push((min_x, min_y, max_x, max_y));
+            }
+        }
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::{weak_rng, Rng};
+
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    use HashType;
+
+    #[test]
+    fn survives_crop() {
+        let mut rng = weak_rng();
+        let blobs = [(10, 20, 60, 50), (90, 30, 50, 90), (20, 120, 60, 60)];
+
+        // Bright blobs with smooth patterns on a noisy dark background.
+        let img = RgbImage::from_fn(240, 200, |x, y| {
+            let blob = blobs.iter().find(|&&(bx, by, bw, bh)| x >= bx && x < bx + bw && y >= by && y < by + bh);
+
+            let val = match blob {
+                Some(&(bx, by, _, _)) => {
+                    let (dx, dy) = ((x - bx) as f64, (y - by) as f64);
+                    (220. + 30. * (dx / 7. + bx as f64).sin() * (dy / 5.).cos()) as u8
+                },
+                None => rng.gen_range(0, 40),
+            };
+
+            Rgb([val, val, val])
+        });
+
+        // Cut off the right 25%, which holds none of the blobs.
+        let cropped = ::image::imageops::crop_imm(&img, 0, 0, 180, 200).to_image();
+
+        let hasher = SegmentHasher::new(Hasher::new(8, HashType::Mean));
+        let (original, cropped) = (hasher.hash(&img), hasher.hash(&cropped));
+
+        assert_eq!(original.segments.len(), 4);
+        assert!(original.matches(&cropped, 8) >= 3, "{:?}\n{:?}", original, cropped);
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Searching for one image inside another, by hashing overlapping windows of the larger one
+//! at several scales.
+//!
+//! Only works with image types that implement `HashImage::crop()`.
+use {CropRect, Hasher, HashImage, ImageHash};
+
+/// Hashes overlapping windows of images at several scales.
+#[derive(Clone, Debug)]
+pub struct TileHasher {
+    hasher: Hasher,
+    scales: Vec<f64>,
+    step: f64,
+}
+
+impl TileHasher {
+    /// Create a `TileHasher` hashing each window with `hasher`.
+    ///
+    /// The windows are, by default, from the full size of the image down to a quarter of it,
+    /// each scale 0.8 times the last, and neighbouring windows are a quarter of their size apart.
+    pub fn new(hasher: Hasher) -> Self {
+        TileHasher {
+            hasher,
+            scales: (0 .. 7).map(|i| 0.8f64.powi(i)).collect(),
+            step: 0.25,
+        }
+    }
+
+    /// Set the sizes of the windows, as fractions of the image size in `(0, 1]`.
+    pub fn scales(self, scales: &[f64]) -> Self {
+        TileHasher { scales: scales.to_vec(), .. self }
+    }
+
+    /// Set the distance between neighbouring windows, as a fraction of their size in `(0, 1]`.
+    /// Smaller steps locate images more precisely, but hash many more windows.
+    pub fn step(self, step: f64) -> Self {
+        TileHasher { step, .. self }
+    }
+
+    /// Hash the windows of `img`, which all have the same aspect ratio as `img` itself.
+    pub fn hash<I: HashImage>(&self, img: &I) -> TileIndex {
+        let (width, height) = img.dimensions();
+        TileIndex { tiles: self.hash_windows(img, width as f64 / height.max(1) as f64) }
+    }
+
+    /// Find where `needle` might be in `haystack`, best match first.
+    ///
+    /// Unlike searching a `TileIndex`, the windows of `haystack` are shaped like `needle`,
+    /// so crops with a different aspect ratio than the whole image are found too.
+    pub fn locate<I: HashImage, J: HashImage>(&self, haystack: &I, needle: &J) -> Vec<Candidate> {
+        let (width, height) = needle.dimensions();
+        let tiles = TileIndex { tiles: self.hash_windows(haystack, width as f64 / height.max(1) as f64) };
+
+        tiles.search(&self.hasher.hash(needle))
+    }
+
+    fn hash_windows<I: HashImage>(&self, img: &I, aspect: f64) -> Vec<Tile> {
+        let (width, height) = img.dimensions();
+        let step = self.step.clamp(0.01, 1.);
+
+        let mut tiles = Vec::new();
+
+        for &scale in &self.scales {
+            let scale = scale.clamp(0., 1.);
+
+            // As large as `scale` allows while fitting in the image.
+            let (mut win_width, mut win_height) = (height as f64 * scale * aspect, height as f64 * scale);
+
+            if win_width > width as f64 * scale {
+                win_width = width as f64 * scale;
+                win_height = win_width / aspect;
+            }
+
+            let (win_width, win_height) = (win_width.round().max(1.) as u32, win_height.round().max(1.) as u32);
+
+            if win_width > width || win_height > height {
+                continue;
+            }
+
+            for y in positions(height, win_height, step) {
+                for x in positions(width, win_width, step) {
+                    let rect = CropRect { x, y, width: win_width, height: win_height };
+
+                    if let Some(window) = img.crop(x, y, win_width, win_height) {
+                        tiles.push(Tile { rect, hash: self.hasher.hash(&window) });
+                    }
+                }
+            }
+        }
+
+        tiles
+    }
+}
+
+/// The offsets of windows of `window` pixels along `len`, `step * window` apart and including
+/// the far end.
+fn positions(len: u32, window: u32, step: f64) -> Vec<u32> {
+    let last = len - window;
+    let stride = ((window as f64 * step) as u32).max(1);
+
+    let mut positions: Vec<u32> = (0 ..= last).step_by(stride as usize).collect();
+
+    if positions.last() != Some(&last) {
+        positions.push(last);
+    }
+
+    positions
+}
+
+/// The hash of one window of an image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    /// Where the window is in the image.
+    pub rect: CropRect,
+    /// The hash of the window.
+    pub hash: ImageHash,
+}
+
+/// The hashes of the windows of an image, from `TileHasher::hash()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileIndex {
+    /// The windows, largest first.
+    pub tiles: Vec<Tile>,
+}
+
+/// A place in an image that might hold the image searched for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    /// The window of the image.
+    pub rect: CropRect,
+    /// The distance between the hash of the window and the image searched for.
+    pub dist: usize,
+}
+
+impl TileIndex {
+    /// Rank every window by its distance to `needle`, the hash of the image searched for,
+    /// closest first. Ties go to larger windows.
+    ///
+    /// `needle` must have been hashed with the same `Hasher` settings as the windows.
+    pub fn search(&self, needle: &ImageHash) -> Vec<Candidate> {
+        let mut candidates: Vec<_> = self.tiles.iter()
+            .map(|tile| Candidate { rect: tile.rect, dist: tile.hash.dist(needle) })
+            .collect();
+
+        // Stable, so windows of the same distance stay largest first.
+        candidates.sort_by_key(|candidate| candidate.dist);
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::imageops;
+
+    use super::*;
+
+    use HashType;
+
+    #[test]
+    fn finds_own_window() {
+        let haystack = ::test::gen_test_img(200, 150);
+
+        let hasher = Hasher::new(8, HashType::Gradient);
+        let tile_hasher = TileHasher::new(hasher.clone());
+        let index = tile_hasher.hash(&haystack);
+
+        let tile = &index.tiles[index.tiles.len() / 2];
+        let rect = tile.rect;
+        let needle = imageops::crop_imm(&haystack, rect.x, rect.y, rect.width, rect.height).to_image();
+
+        assert!(rect.width < 200 && rect.height < 150);
+        assert_eq!(index.search(&hasher.hash(&needle))[0], Candidate { rect, dist: 0 });
+    }
+}
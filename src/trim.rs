@@ -0,0 +1,134 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Detection of uniform borders (letterboxing, pillarboxing, frames) to crop before hashing.
+use super::HashImage;
+
+/// A rectangle of an image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CropRect {
+    /// The column of the left edge.
+    pub x: u32,
+    /// The row of the top edge.
+    pub y: u32,
+    /// The width of the rectangle.
+    pub width: u32,
+    /// The height of the rectangle.
+    pub height: u32,
+}
+
+impl CropRect {
+    /// The rectangle covering all of an image of the given dimensions.
+    pub fn full((width, height): (u32, u32)) -> Self {
+        CropRect { x: 0, y: 0, width, height }
+    }
+}
+
+/// Settings for cropping near-uniform borders off images before hashing them,
+/// set with `Hasher::trim_borders()`.
+///
+/// Each side is trimmed independently, one row or column at a time, for as long as every pixel
+/// of it is within `tolerance` of the border's brightness: that of the first pixel of the
+/// outermost row or column on that side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrimBorders {
+    tolerance: u8,
+    max_fraction: f32,
+}
+
+impl Default for TrimBorders {
+    /// A tolerance of 16 and a maximum of a quarter of the image off each side.
+    fn default() -> Self {
+        TrimBorders {
+            tolerance: 16,
+            max_fraction: 0.25,
+        }
+    }
+}
+
+impl TrimBorders {
+    /// Create the default settings; see `Default`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how far, on a scale of 0 to 255, a pixel's brightness may be from the border's to
+    /// still count as part of it.
+    pub fn tolerance(self, tolerance: u8) -> Self {
+        TrimBorders { tolerance, .. self }
+    }
+
+    /// Set the largest fraction of the width (or height) that may be trimmed from each side.
+    /// Clamped to `[0, 0.5)`.
+    pub fn max_fraction(self, max_fraction: f32) -> Self {
+        TrimBorders { max_fraction: max_fraction.clamp(0., 0.499), .. self }
+    }
+
+    /// A short string identifying these settings in `Hasher::fingerprint()`.
+    pub(crate) fn fingerprint(&self) -> String {
+        format!("trim{}-{}", self.tolerance, self.max_fraction)
+    }
+
+    /// Find the part of `img` left after trimming its borders.
+    pub fn detect<I: HashImage>(&self, img: &I) -> CropRect {
+        let (width, height) = img.dimensions();
+        let (w, h) = (width as usize, height as usize);
+
+        if w == 0 || h == 0 {
+            return CropRect::full((width, height));
+        }
+
+        let mut luma = vec![0u8; w * h];
+
+        img.grayscale().foreach_pixel(|x, y, px| luma[y as usize * w + x as usize] = px[0]);
+
+        let max_x = (w as f32 * self.max_fraction) as usize;
+        let max_y = (h as f32 * self.max_fraction) as usize;
+
+        let tolerance = self.tolerance;
+        let border = |mut pixels: &mut dyn Iterator<Item = u8>, color: u8| {
+            Iterator::all(&mut pixels, |px| px.abs_diff(color) <= tolerance)
+        };
+
+        let row = |y: usize| luma[y * w .. (y + 1) * w].iter().cloned();
+        let column = |x: usize| luma[x ..].iter().step_by(w).cloned();
+
+        let (top_color, bottom_color) = (luma[0], luma[(h - 1) * w]);
+        let (left_color, right_color) = (luma[0], luma[w - 1]);
+
+        let top = (0 .. max_y).take_while(|&y| border(&mut row(y), top_color)).count();
+        let bottom = (0 .. max_y).take_while(|&y| border(&mut row(h - 1 - y), bottom_color)).count();
+        let left = (0 .. max_x).take_while(|&x| border(&mut column(x), left_color)).count();
+        let right = (0 .. max_x).take_while(|&x| border(&mut column(w - 1 - x), right_color)).count();
+
+        CropRect {
+            x: left as u32,
+            y: top as u32,
+            width: (w - left - right) as u32,
+            height: (h - top - bottom) as u32,
+        }
+    }
+}
+
+/// Trim the borders of `img` per `trim`, if set and the image type supports cropping, and pass
+/// the result to `hash_fn`, returning its result and the rectangle that was hashed.
+pub(crate) fn with_trimmed<I, R, F>(trim: Option<&TrimBorders>, img: &I, hash_fn: F) -> (R, CropRect)
+where I: HashImage, F: FnOnce(&I) -> R {
+    let full = CropRect::full(img.dimensions());
+
+    if let Some(trim) = trim {
+        let rect = trim.detect(img);
+
+        if rect != full {
+            if let Some(cropped) = img.crop(rect.x, rect.y, rect.width, rect.height) {
+                return (hash_fn(&cropped), rect);
+            }
+        }
+    }
+
+    (hash_fn(img), full)
+}
@@ -0,0 +1,294 @@
+// Copyright (c) 2015-2018 The `img_hash` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Fingerprinting of videos as sequences of frame hashes.
+//!
+//! Nothing here decodes video; feed `VideoHasher::hash_frames()` the decoded frames (e.g. as
+//! `YuvImage`s) with their presentation timestamps. Two fingerprints can then be compared as a
+//! whole with `VideoFingerprint::aggregate()`, or searched for shared segments with
+//! `VideoFingerprint::align()`, which tolerates different start offsets and dropped frames.
+use bit_vec::BitVec;
+
+use {Hasher, HashImage, ImageHash};
+
+use std::ops::Range;
+use std::time::Duration;
+
+/// Hashes a subset of the frames of a video, sampled at a fixed rate.
+#[derive(Clone, Debug)]
+pub struct VideoHasher {
+    hasher: Hasher,
+    interval: Duration,
+}
+
+impl VideoHasher {
+    /// Create a `VideoHasher` hashing frames with `hasher`, sampling one frame per second.
+    pub fn new(hasher: Hasher) -> Self {
+        VideoHasher {
+            hasher,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Sample `fps` frames per second of video.
+    ///
+    /// Panics if `fps` is not positive and finite, or so small that its interval doesn't fit in
+    /// a `Duration`.
+    pub fn sample_rate(self, fps: f64) -> Self {
+        match Duration::try_from_secs_f64(1. / fps) {
+            Ok(interval) if fps > 0. && fps.is_finite() => self.sample_interval(interval),
+            _ => panic!("sample rate must be positive, got {}", fps),
+        }
+    }
+
+    /// Sample one frame every `interval` of video. An interval of zero samples every frame.
+    pub fn sample_interval(self, interval: Duration) -> Self {
+        VideoHasher { interval, .. self }
+    }
+
+    /// Get the `Hasher` used for each frame.
+    pub fn hasher(&self) -> &Hasher {
+        &self.hasher
+    }
+
+    /// Hash the frames yielded by `frames` as `(timestamp, frame)`, in presentation order.
+    ///
+    /// The first frame at or after each multiple of the sample interval is hashed; the rest are
+    /// skipped without being touched, so frames can be decoded lazily by the iterator.
+    pub fn hash_frames<I, F>(&self, frames: F) -> VideoFingerprint
+    where I: HashImage, F: IntoIterator<Item = (Duration, I)> {
+        let interval = self.interval.as_nanos();
+        let mut next_sample = Duration::from_secs(0);
+        let mut sampled = Vec::new();
+
+        for (timestamp, frame) in frames {
+            if timestamp < next_sample {
+                continue;
+            }
+
+            sampled.push(FrameHash {
+                timestamp,
+                hash: self.hasher.hash(&frame),
+            });
+
+            // A zero interval samples every frame.
+            if let Some(periods) = timestamp.as_nanos().checked_div(interval) {
+                next_sample = Duration::from_nanos(((periods + 1) * interval) as u64);
+            }
+        }
+
+        VideoFingerprint { frames: sampled }
+    }
+}
+
+/// The hash of a single sampled frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameHash {
+    /// The presentation timestamp of the frame.
+    pub timestamp: Duration,
+    /// The hash of the frame.
+    pub hash: ImageHash,
+}
+
+/// The sampled frame hashes of a video, in presentation order.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct VideoFingerprint {
+    /// The frame hashes.
+    pub frames: Vec<FrameHash>,
+}
+
+/// A run of frames found in both of two `VideoFingerprint`s by `VideoFingerprint::align()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchedSegment {
+    /// The indices of the frames of the segment in the fingerprint `align()` was called on.
+    pub frames_a: Range<usize>,
+    /// The indices of the frames of the segment in the other fingerprint.
+    pub frames_b: Range<usize>,
+    /// The number of frame pairs within the distance threshold. Lower than the length of
+    /// either range if frames were dropped, or didn't match.
+    pub matched: usize,
+}
+
+const MATCH_SCORE: i32 = 2;
+const MISMATCH_PENALTY: i32 = 1;
+const GAP_PENALTY: i32 = 1;
+
+/// One cell of the alignment matrix.
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    score: i32,
+    start: (usize, usize),
+    matched: usize,
+}
+
+impl VideoFingerprint {
+    /// Get the number of sampled frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns `true` if no frames were sampled.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Combine all frame hashes into a single hash of the whole video, by taking the majority
+    /// of each bit over all frames.
+    ///
+    /// Returns `None` if there are no frames.
+    pub fn aggregate(&self) -> Option<ImageHash> {
+        let first = &self.frames.first()?.hash;
+        let mut counts = vec![0usize; first.bitv.len()];
+
+        for frame in &self.frames {
+            for (count, bit) in counts.iter_mut().zip(frame.hash.bitv.iter()) {
+                *count += bit as usize;
+            }
+        }
+
+        Some(ImageHash {
+            bitv: counts.iter().map(|&count| count * 2 > self.frames.len()).collect::<BitVec>(),
+            hash_type: first.hash_type,
+        })
+    }
+
+    /// Find the segments shared by this video and `other`, best first.
+    ///
+    /// Two frames match if their hashes are at most `max_dist` bits apart. Segments are found
+    /// by local sequence alignment (Smith-Waterman), so they may start at any offset in either
+    /// video and skip over frames dropped from one of them. Each frame belongs to at most one
+    /// segment, and segments with fewer than `min_matched` matching frames are not returned.
+    ///
+    /// Takes time proportional to the product of the two lengths for each segment found.
+    /// Both fingerprints must have been made with the same `Hasher` settings.
+    pub fn align(&self, other: &VideoFingerprint, max_dist: usize, min_matched: usize) -> Vec<MatchedSegment> {
+        let (len_a, len_b) = (self.len(), other.len());
+
+        let matches: Vec<bool> = self.frames.iter()
+            .flat_map(|a| other.frames.iter().map(move |b| a.hash.dist(&b.hash) <= max_dist))
+            .collect();
+
+        let mut used_a = vec![false; len_a];
+        let mut used_b = vec![false; len_b];
+        let mut segments = Vec::new();
+
+        loop {
+            let mut prev_row = vec![Cell::default(); len_b + 1];
+            let mut row = prev_row.clone();
+            let mut best = (Cell::default(), (0, 0));
+
+            for i in 0 .. len_a {
+                for j in 0 .. len_b {
+                    row[j + 1] = if used_a[i] || used_b[j] {
+                        Cell::default()
+                    } else {
+                        let is_match = matches[i * len_b + j];
+                        let diag = prev_row[j];
+
+                        let start = if diag.score > 0 { diag.start } else { (i, j) };
+                        let mut cell = Cell {
+                            score: diag.score.max(0) + if is_match { MATCH_SCORE } else { -MISMATCH_PENALTY },
+                            start,
+                            matched: if diag.score > 0 { diag.matched } else { 0 } + is_match as usize,
+                        };
+
+                        for gap in [prev_row[j + 1], row[j]] {
+                            if gap.score - GAP_PENALTY > cell.score {
+                                cell = Cell { score: gap.score - GAP_PENALTY, .. gap };
+                            }
+                        }
+
+                        if cell.score > 0 { cell } else { Cell::default() }
+                    };
+
+                    if row[j + 1].score > best.0.score {
+                        best = (row[j + 1], (i, j));
+                    }
+                }
+
+                ::std::mem::swap(&mut prev_row, &mut row);
+            }
+
+            let (cell, (end_a, end_b)) = best;
+
+            if cell.score == 0 || cell.matched < min_matched.max(1) {
+                break;
+            }
+
+            let segment = MatchedSegment {
+                frames_a: cell.start.0 .. end_a + 1,
+                frames_b: cell.start.1 .. end_b + 1,
+                matched: cell.matched,
+            };
+
+            for used in &mut used_a[segment.frames_a.clone()] { *used = true; }
+            for used in &mut used_b[segment.frames_b.clone()] { *used = true; }
+
+            segments.push(segment);
+        }
+
+        segments
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+
+    use self::rand::{weak_rng, Rng};
+
+    use super::*;
+
+    use {HashType, PixelFormat, RawImage};
+
+    const SIZE: u32 = 32;
+
+    fn gen_frames(count: usize) -> Vec<Vec<u8>> {
+        let mut rng = weak_rng();
+
+        (0 .. count).map(|_| {
+            let mut frame = vec![0; (SIZE * SIZE) as usize];
+            rng.fill_bytes(&mut frame);
+            frame
+        }).collect()
+    }
+
+    fn fingerprint<'a, F>(frames: F) -> VideoFingerprint where F: IntoIterator<Item = &'a Vec<u8>> {
+        let hasher = VideoHasher::new(Hasher::new(8, HashType::Gradient)).sample_rate(10.);
+
+        hasher.hash_frames(frames.into_iter().enumerate().map(|(i, frame)| (
+            Duration::from_millis(i as u64 * 100),
+            RawImage::new(frame, SIZE, SIZE, PixelFormat::Gray8).unwrap(),
+        )))
+    }
+
+    #[test]
+    fn align_with_offset_and_dropped_frames() {
+        let frames = gen_frames(60);
+
+        let original = fingerprint(&frames);
+        // A clip of frames 20 .. 50 with every seventh frame dropped, after 5 unrelated frames.
+        let unrelated = gen_frames(5);
+        let clip = fingerprint(unrelated.iter().chain(
+            frames[20 .. 50].iter().enumerate().filter(|&(i, _)| i % 7 != 3).map(|(_, f)| f)
+        ));
+
+        let segments = original.align(&clip, 4, 10);
+
+        assert_eq!(segments, vec![MatchedSegment {
+            frames_a: 20 .. 50,
+            frames_b: 5 .. 31,
+            matched: 26,
+        }]);
+    }
+
+    #[test]
+    #[should_panic(expected = "sample rate must be positive")]
+    fn tiny_sample_rate_panics() {
+        VideoHasher::new(Hasher::new(8, HashType::Gradient)).sample_rate(1e-300);
+    }
+}